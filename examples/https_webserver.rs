@@ -1,12 +1,15 @@
 use pass_tool::{
     actions::{
         action, command, create_dir_perm, delete_file, install_apt_packages, many, perm,
-        start_service, stop_service, write_file, write_file_perm,
+        start_service, stop_service, write_file_perm, write_template,
     },
     checks::{
-        check, is_file, path_is_missing, service_is_inactive, stdout_contains_once, user_is_root,
+        check, is_file, is_rendered_template, path_is_missing, service_is_inactive,
+        stdout_contains_once, user_is_root,
     },
-    instruction, run_cli_with_input, Playbook,
+    instruction, run_cli_with_input,
+    template::{template, vars},
+    Playbook,
 };
 
 const ABOUT: &str = "Example installing nginx webserver with letsencrypt certificate.
@@ -31,10 +34,8 @@ fn main() {
             let [Some(email), Some(domain)] = [parts.next(), parts.next()] else {
                 return Err(HELP.to_owned());
             };
-            let nginx_conf = NGINX_CONF
-                .split("{--domain--}")
-                .collect::<Vec<_>>()
-                .join(domain);
+            let nginx_conf = template(NGINX_CONF).strict();
+            let nginx_vars = vars().var("domain", domain);
             Ok(Playbook::new(
                 "Install and configure nginx with https",
                 ABOUT,
@@ -64,10 +65,11 @@ fn main() {
                             command(["ufw", "default", "allow", "outgoing"]),
                         ]),
                     ))
-                    .with_env(check(
+                    .when(check(
                         "Firewall is inactive",
                         stdout_contains_once(["ufw", "status"], "Status: inactive"),
-                    )),
+                    ))
+                    .tag("firewall"),
                     instruction(action("Stop nginx", stop_service("nginx"))),
                     instruction(action(
                         "Request ssl certificate",
@@ -87,7 +89,8 @@ fn main() {
                     .confirm(check(
                         "Ssl certificate exists",
                         is_file(format!("/etc/letsencrypt/live/{domain}/fullchain.pem")),
-                    )),
+                    ))
+                    .tag("tls"),
                     instruction(action(
                         "Enable certbot renew",
                         write_file_perm(
@@ -99,7 +102,8 @@ fn main() {
                     .confirm(check(
                         "Certbot renew is enabled",
                         is_file("/etc/cron.weekly/certbot-renew"),
-                    )),
+                    ))
+                    .tag("tls"),
                     instruction(action(
                         "Delete default nginx site",
                         delete_file("/etc/nginx/sites-enabled/default"),
@@ -110,11 +114,19 @@ fn main() {
                     )),
                     instruction(action(
                         "Create pass demo site nginx configuration",
-                        write_file("/etc/nginx/sites-enabled/pass-demo", nginx_conf),
+                        write_template(
+                            "/etc/nginx/sites-enabled/pass-demo",
+                            nginx_conf.clone(),
+                            nginx_vars.clone(),
+                        ),
                     ))
                     .confirm(check(
-                        "Pass demo site nginx configuration is exists",
-                        is_file("/etc/nginx/sites-enabled/pass-demo"),
+                        "Pass demo site nginx configuration is up to date",
+                        is_rendered_template(
+                            "/etc/nginx/sites-enabled/pass-demo",
+                            nginx_conf,
+                            nginx_vars,
+                        ),
                     )),
                     instruction(action(
                         "Create website files",
@@ -128,11 +140,12 @@ fn main() {
                         ]),
                     )),
                     instruction(action("Start nginx", start_service("nginx"))),
-                    instruction(action("Start firewall", start_service("ufw"))),
+                    instruction(action("Start firewall", start_service("ufw"))).tag("firewall"),
                     instruction(action(
                         "Enable firewall",
                         command(["ufw", "--force", "enable"]),
-                    )),
+                    ))
+                    .tag("firewall"),
                 ],
             ))
         },
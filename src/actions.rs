@@ -1,16 +1,48 @@
 use std::{
     ffi::OsString,
-    io::ErrorKind,
-    os::unix::fs::{chown, PermissionsExt},
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    os::unix::fs::{chown, fchown, MetadataExt, OpenOptionsExt, PermissionsExt},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    interfaces::{Action, ActionResult},
+    backup::backup_file,
+    dir_context::{in_dir, with_dir_lock},
+    facts::{set_fact, with_facts, Facts},
+    interfaces::{Action, ActionResult, ExecContext},
+    lines::{self, LinePosition},
     pattern::Pattern,
-    process::{norm_cmd, run},
+    process::{deadline, norm_cmd, run, run_spec, with_deadline, ProcessResult, ProcessSpec},
+    template::{Template, Vars},
 };
 
+/// Failed result of [Action::run_explained] with provided reason
+pub(crate) fn fail<Reason>(reason: Reason) -> (ActionResult, Option<String>)
+where
+    Reason: Into<String>,
+{
+    (ActionResult::Fail, Some(reason.into()))
+}
+
+/// Result of action running external process
+fn explain_process(result: ProcessResult) -> (ActionResult, Option<String>) {
+    match result.explain() {
+        Some(reason) => fail(reason),
+        None => (ActionResult::Ok, None),
+    }
+}
+
+/// Reason of failed nested action, prefixed with its name
+fn with_name(name: &str, reason: Option<String>) -> String {
+    match reason {
+        Some(reason) => format!("`{name}` failed: {reason}"),
+        None => format!("`{name}` failed"),
+    }
+}
+
 /// Action which does nothing and always succeeds
 pub struct AlwaysOk;
 
@@ -22,8 +54,8 @@ impl Action for AlwaysOk {
     fn name(&self) -> &str {
         Self::NAME
     }
-    fn run(&self) -> ActionResult {
-        ActionResult::Ok
+    fn run_in(&self, _ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        (ActionResult::Ok, None)
     }
     fn into_action(self) -> Box<dyn Action> {
         Box::new(self)
@@ -46,8 +78,8 @@ impl Action for AlwaysFail {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        ActionResult::Fail
+    fn run_in(&self, _ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        (ActionResult::Fail, None)
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -77,8 +109,16 @@ impl Action for Named {
         &self.name
     }
 
-    fn run(&self) -> ActionResult {
-        self.action.run()
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        self.action.run_in(ctx)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        self.action.undo_in(ctx)
+    }
+
+    fn kind(&self) -> &str {
+        self.action.kind()
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -112,13 +152,24 @@ impl Action for Many {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
         for action in &self.actions {
-            if action.run() == ActionResult::Fail {
-                return ActionResult::Fail;
+            if let (ActionResult::Fail, reason) = action.run_in(ctx) {
+                return fail(with_name(action.name(), reason));
             }
         }
-        ActionResult::Ok
+        (ActionResult::Ok, None)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        // can be undone only if every action can be undone
+        let mut undo = self
+            .actions
+            .iter()
+            .map(|a| a.undo_in(ctx))
+            .collect::<Option<Vec<_>>>()?;
+        undo.reverse();
+        Some(many(undo))
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -134,16 +185,148 @@ where
     Many::new(actions.into()).into_action()
 }
 
-/// Runs external process using provided command
+/// Delay between attempts of retried action
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backoff {
+    /// Retry immediately
+    #[default]
+    None,
+    /// Wait same time before each retry
+    Fixed(Duration),
+    /// Wait provided time before first retry, doubling it for each next retry
+    Exponential(Duration),
+}
+
+impl Backoff {
+    /// Returns delay before retry, `retry` starts from `1` (first retry is
+    /// second attempt)
+    pub fn delay(&self, retry: usize) -> Duration {
+        match self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(d) => *d,
+            Backoff::Exponential(d) => {
+                let power = retry.saturating_sub(1).min(u32::MAX as usize) as u32;
+                d.saturating_mul(2u32.checked_pow(power).unwrap_or(u32::MAX))
+            }
+        }
+    }
+}
+
+/// Runs action again if it fails, up to provided number of attempts. Attempts
+/// are not reported to observer while running (use
+/// [Instruction::retry](crate::playbook::Instruction::retry) for that), failure reason
+/// lists reasons of all attempts
+pub struct Retry {
+    action: Box<dyn Action>,
+    attempts: usize,
+    backoff: Backoff,
+}
+
+impl Retry {
+    const NAME: &'static str = "Retry";
+
+    pub fn new(action: Box<dyn Action>, attempts: usize, backoff: Backoff) -> Self {
+        Self {
+            action,
+            attempts,
+            backoff,
+        }
+    }
+}
+
+impl Action for Retry {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let attempts = self.attempts.max(1);
+        let mut reasons = vec![];
+        for attempt in 1..=attempts {
+            if attempt > 1 {
+                std::thread::sleep(self.backoff.delay(attempt - 1));
+            }
+            match self.action.run_in(ctx) {
+                (ActionResult::Ok, _) => return (ActionResult::Ok, None),
+                (ActionResult::Fail, reason) => reasons.push(format!(
+                    "attempt {attempt}: {}",
+                    reason.as_deref().unwrap_or("failed")
+                )),
+            }
+        }
+        fail(format!("failed {attempts} times\n{}", reasons.join("\n")))
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        self.action.undo_in(ctx)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [Retry], `attempts` is total number of runs (at least one)
+pub fn retry(action: Box<dyn Action>, attempts: usize, backoff: Backoff) -> Box<dyn Action> {
+    Retry::new(action, attempts, backoff).into_action()
+}
+
+/// Fails action if it runs longer than provided time, external processes
+/// started by action are killed when time is out (see
+/// [with_deadline](crate::process::with_deadline)), other work of action can't
+/// be interrupted, but result will be [ActionResult::Fail]
+pub struct Timeout {
+    action: Box<dyn Action>,
+    timeout: Duration,
+}
+
+impl Timeout {
+    const NAME: &'static str = "Timeout";
+
+    pub fn new(timeout: Duration, action: Box<dyn Action>) -> Self {
+        Self { action, timeout }
+    }
+}
+
+impl Action for Timeout {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let deadline = Instant::now() + self.timeout;
+        let result = with_deadline(deadline, || self.action.run_in(ctx));
+        if Instant::now() > deadline {
+            fail(format!("timed out after {:?}", self.timeout))
+        } else {
+            result
+        }
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        self.action.undo_in(ctx)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [Timeout]
+pub fn timeout(timeout: Duration, action: Box<dyn Action>) -> Box<dyn Action> {
+    Timeout::new(timeout, action).into_action()
+}
+
+/// Runs external process using provided command or [ProcessSpec]
 pub struct Command {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
 }
 
 impl Command {
     const NAME: &'static str = "Command";
 
-    pub fn new(cmd: Vec<String>) -> Self {
-        Self { cmd }
+    pub fn new(spec: ProcessSpec) -> Self {
+        Self { spec }
     }
 }
 
@@ -152,13 +335,8 @@ impl Action for Command {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        let result = run(&self.cmd);
-        if result.ok() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
-        }
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        explain_process(run_spec(&self.spec.in_context(ctx)))
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -167,12 +345,110 @@ impl Action for Command {
 }
 
 /// init [Command]
-pub fn command<Cmd, Arg>(cmd: Cmd) -> Box<dyn Action>
+pub fn command<Cmd>(cmd: Cmd) -> Box<dyn Action>
+where
+    Cmd: Into<ProcessSpec>,
+{
+    Command::new(cmd.into()).into_action()
+}
+
+/// Runs external process and stores its stdout (without leading and trailing
+/// whitespaces) as fact, fails if process fails or stdout is not utf8
+pub struct CommandOutputInto {
+    fact: String,
+    spec: ProcessSpec,
+}
+
+impl CommandOutputInto {
+    const NAME: &'static str = "CommandOutputInto";
+
+    pub fn new(fact: String, spec: ProcessSpec) -> Self {
+        Self { fact, spec }
+    }
+}
+
+impl Action for CommandOutputInto {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        if let Some(reason) = result.explain() {
+            return fail(reason);
+        }
+        let Some(output) = result.output else {
+            return fail("process output is not captured");
+        };
+        let Ok(stdout) = String::from_utf8(output.stdout) else {
+            return fail("stdout is not utf8 text");
+        };
+        set_fact(self.fact.clone(), stdout.trim());
+        (ActionResult::Ok, None)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [CommandOutputInto]
+pub fn command_output_into<Fact, Cmd>(fact: Fact, cmd: Cmd) -> Box<dyn Action>
+where
+    Fact: Into<String>,
+    Cmd: Into<ProcessSpec>,
+{
+    CommandOutputInto::new(fact.into(), cmd.into()).into_action()
+}
+
+/// Function building action from facts
+pub type BuildAction = Box<dyn Fn(&Facts) -> Box<dyn Action> + Send + Sync>;
+
+/// Action which is built right before it runs, using facts stored by previous
+/// actions
+pub struct Lazy {
+    name: String,
+    build: BuildAction,
+}
+
+impl Lazy {
+    pub fn new(name: String, build: BuildAction) -> Self {
+        Self { name, build }
+    }
+}
+
+impl Action for Lazy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        with_facts(|facts| (self.build)(facts)).run_in(ctx)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        with_facts(|facts| (self.build)(facts)).undo_in(ctx)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [Lazy], example:
+///
+/// ```
+/// # use pass_tool::actions::{command, lazy};
+/// lazy("Allow ssh", |facts| {
+///     command(["ufw", "allow", "from", facts.get("admin_ip").unwrap_or_default()])
+/// });
+/// ```
+pub fn lazy<Name, Build>(name: Name, build: Build) -> Box<dyn Action>
 where
-    Arg: Into<String>,
-    Cmd: Into<Vec<Arg>>,
+    Name: Into<String>,
+    Build: Fn(&Facts) -> Box<dyn Action> + Send + Sync + 'static,
 {
-    Command::new(norm_cmd(cmd)).into_action()
+    Lazy::new(name.into(), Box::new(build)).into_action()
 }
 
 /// Inverts result of another action, Ok becomes Fail
@@ -193,10 +469,10 @@ impl Action for Invert {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        match self.action.run() {
-            ActionResult::Ok => ActionResult::Fail,
-            ActionResult::Fail => ActionResult::Ok,
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        match self.action.run_in(ctx).0 {
+            ActionResult::Ok => fail(format!("`{}` succeed", self.action.name())),
+            ActionResult::Fail => (ActionResult::Ok, None),
         }
     }
 
@@ -228,16 +504,11 @@ impl Action for InstallAptPackages {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
+    fn run_in(&self, _ctx: &ExecContext) -> (ActionResult, Option<String>) {
         let mut apt_cmd = vec!["apt", "install", "-y"];
         let mut packages: Vec<&str> = self.packages.iter().map(|p| p.as_str()).collect();
         apt_cmd.append(&mut packages);
-        let result = run(&norm_cmd(apt_cmd));
-        if result.ok() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
-        }
+        explain_process(run(&norm_cmd(apt_cmd)))
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -273,20 +544,29 @@ impl Action for DeleteFile {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        match std::fs::remove_file(&self.path) {
-            Ok(_) => ActionResult::Ok,
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        if let Err(reason) = backup_file(&path) {
+            return fail(reason);
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => (ActionResult::Ok, None),
             Err(e) => {
                 if let ErrorKind::NotFound = e.kind() {
                     // file does not exist, nothing to delete
-                    ActionResult::Ok
+                    (ActionResult::Ok, None)
                 } else {
-                    ActionResult::Fail
+                    fail(format!("can't delete file: {e}"))
                 }
             }
         }
     }
 
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
+    }
+
     fn into_action(self) -> Box<dyn Action> {
         Box::new(self)
     }
@@ -345,6 +625,21 @@ impl PathPermissions {
         self.user(owner.clone()).group(owner)
     }
 
+    /// Reads current access mode and owners of path
+    pub fn of<FilePath>(path: FilePath) -> Option<Self>
+    where
+        FilePath: AsRef<Path>,
+    {
+        let metadata = std::fs::metadata(path.as_ref()).ok()?;
+        let user = nix::unistd::User::from_uid(metadata.uid().into()).ok()??;
+        let group = nix::unistd::Group::from_gid(metadata.gid().into()).ok()??;
+        Some(Self::new(
+            metadata.permissions().mode() & 0o7777,
+            user.name,
+            group.name,
+        ))
+    }
+
     pub fn apply<FilePath>(&self, path: FilePath) -> Option<()>
     where
         FilePath: AsRef<Path>,
@@ -376,7 +671,187 @@ where
     PathPermissions::default().access(access_mode).owner(owner)
 }
 
-/// Write provided data into file
+/// Creates action which restores current state of file: its content and
+/// permissions, or deletes file if it does not exist yet
+fn restore_file(path: &Path) -> Option<Box<dyn Action>> {
+    if !path.try_exists().ok()? {
+        return Some(delete_file(path));
+    }
+    if !path.is_file() {
+        return None;
+    }
+    let perm = PathPermissions::of(path)?;
+    let snapshot = snapshot_file(path)?;
+    Some(
+        RestoreFile {
+            path: path.to_path_buf(),
+            snapshot,
+            perm,
+        }
+        .into_action(),
+    )
+}
+
+/// Copies file into private temporary file without reading it into memory
+fn snapshot_file(path: &Path) -> Option<PathBuf> {
+    let snapshot = std::env::temp_dir().join(format!(
+        "pass-undo-{}-{}",
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut source = std::fs::File::open(path).ok()?;
+    // snapshot can contain secrets
+    let mut target = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&snapshot)
+        .ok()?;
+    if std::io::copy(&mut source, &mut target).is_err() {
+        let _ = std::fs::remove_file(&snapshot);
+        return None;
+    }
+    Some(snapshot)
+}
+
+/// Writes back content of file saved by [restore_file], snapshot is removed
+/// when action is dropped
+struct RestoreFile {
+    path: PathBuf,
+    snapshot: PathBuf,
+    perm: PathPermissions,
+}
+
+impl RestoreFile {
+    const NAME: &'static str = "RestoreFile";
+}
+
+impl Action for RestoreFile {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, _ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let content = match std::fs::read(&self.snapshot) {
+            Ok(content) => content,
+            Err(e) => return fail(format!("can't read snapshot: {e}")),
+        };
+        match write_atomic(&self.path, &content, &self.perm) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(reason) => fail(reason),
+        }
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+impl Drop for RestoreFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.snapshot);
+    }
+}
+
+/// Counter making names of temporary files unique inside of process
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes data into temporary file in the same directory and renames it over
+/// `path`, so file is never seen partially written. Access mode and owners of
+/// replaced file are kept unless set in `perm`, symlink is replaced through
+pub(crate) fn write_atomic(path: &Path, data: &[u8], perm: &PathPermissions) -> Result<(), String> {
+    let path = link_target(path)?;
+    let Some(name) = path.file_name() else {
+        return Err("file path has no file name".to_owned());
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = dir.join(format!(
+        ".{}.pass-{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = write_temp(&path, &temp, data, perm).and_then(|_| {
+        std::fs::rename(&temp, &path).map_err(|e| format!("can't replace file: {e}"))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    } else if let Ok(dir) = std::fs::File::open(dir) {
+        // makes rename durable
+        let _ = dir.sync_all();
+    }
+    result
+}
+
+/// Follows symlinks to path they point to, target may not exist yet (then it is
+/// created same way as with [std::fs::write])
+fn link_target(path: &Path) -> Result<PathBuf, String> {
+    let mut path = path.to_path_buf();
+    // same limit as in linux
+    for _ in 0..40 {
+        match std::fs::symlink_metadata(&path) {
+            Ok(m) if m.file_type().is_symlink() => {
+                let target =
+                    std::fs::read_link(&path).map_err(|e| format!("can't resolve symlink: {e}"))?;
+                // relative target is relative to directory of link
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err("can't resolve symlink: too many levels of symbolic links".to_owned())
+}
+
+fn write_temp(path: &Path, temp: &Path, data: &[u8], perm: &PathPermissions) -> Result<(), String> {
+    let replaced = std::fs::metadata(path).ok();
+    // new file without access mode gets the same mode as with std::fs::write,
+    // otherwise data is not readable by others until mode is set
+    let mode = if replaced.is_none() && perm.access_mode.is_none() {
+        0o666
+    } else {
+        0o600
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(temp)
+        .map_err(|e| format!("can't write file: {e}"))?;
+    file.write_all(data)
+        .map_err(|e| format!("can't write file: {e}"))?;
+    if let Some(m) = &replaced {
+        // changing owner fails without privileges, then file is owned by
+        // current user as any new file
+        let _ = fchown(&file, Some(m.uid()), Some(m.gid()));
+        file.set_permissions(std::fs::Permissions::from_mode(m.mode() & 0o7777))
+            .map_err(|_| "can't set permissions".to_owned())?;
+    }
+    perm.apply(temp)
+        .ok_or_else(|| "can't set permissions".to_owned())?;
+    file.sync_all()
+        .map_err(|e| format!("can't write file: {e}"))
+}
+
+/// Replaces content of existing file keeping its permissions, file is backed
+/// up first (see [backup](crate::backup))
+fn rewrite_file(path: &Path, content: &[u8]) -> (ActionResult, Option<String>) {
+    if let Err(reason) = backup_file(path) {
+        return fail(reason);
+    }
+    match write_atomic(path, content, &PathPermissions::default()) {
+        Ok(_) => (ActionResult::Ok, None),
+        Err(reason) => fail(reason),
+    }
+}
+
+/// Write provided data into file, data is written into temporary file which
+/// then replaces target, so file is never seen partially written
 pub struct WriteFile {
     path: PathBuf,
     data: Vec<u8>,
@@ -396,20 +871,22 @@ impl Action for WriteFile {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        // todo: use exclusive file access? no support in std, need external lib
-        if std::fs::write(&self.path, &self.data).is_ok() {
-            // if permissions not set this is noop
-            if self.perm.apply(&self.path).is_some() {
-                ActionResult::Ok
-            } else {
-                ActionResult::Fail
-            }
-        } else {
-            ActionResult::Fail
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        if let Err(reason) = backup_file(&path) {
+            return fail(reason);
+        }
+        match write_atomic(&path, &self.data, &self.perm) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(reason) => fail(reason),
         }
     }
 
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
+    }
+
     fn into_action(self) -> Box<dyn Action> {
         Box::new(self)
     }
@@ -434,40 +911,426 @@ where
     FilePath: Into<PathBuf>,
     Content: Into<Vec<u8>>,
 {
-    WriteFile::new(path.into(), content.into(), perm).into_action()
+    WriteFile::new(path.into(), content.into(), perm).into_action()
+}
+
+/// Renders template (see [template](crate::template)) and writes result into
+/// file as [WriteFile] does, fails if template can't be rendered
+pub struct WriteTemplate {
+    path: PathBuf,
+    template: Template,
+    vars: Vars,
+    perm: PathPermissions,
+}
+
+impl WriteTemplate {
+    const NAME: &'static str = "WriteTemplate";
+
+    pub fn new(path: PathBuf, template: Template, vars: Vars, perm: PathPermissions) -> Self {
+        Self {
+            path,
+            template,
+            vars,
+            perm,
+        }
+    }
+}
+
+impl Action for WriteTemplate {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        match self.template.render(&self.vars) {
+            Ok(content) => {
+                WriteFile::new(self.path.clone(), content.into(), self.perm.clone()).run_in(ctx)
+            }
+            Err(e) => fail(format!("can't render template: {e}")),
+        }
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [WriteTemplate]
+pub fn write_template<FilePath, Tpl>(path: FilePath, template: Tpl, vars: Vars) -> Box<dyn Action>
+where
+    FilePath: Into<PathBuf>,
+    Tpl: Into<Template>,
+{
+    WriteTemplate::new(
+        path.into(),
+        template.into(),
+        vars,
+        PathPermissions::default(),
+    )
+    .into_action()
+}
+
+/// init [WriteTemplate] with setting custom permission for file
+pub fn write_template_perm<FilePath, Tpl>(
+    path: FilePath,
+    template: Tpl,
+    vars: Vars,
+    perm: PathPermissions,
+) -> Box<dyn Action>
+where
+    FilePath: Into<PathBuf>,
+    Tpl: Into<Template>,
+{
+    WriteTemplate::new(path.into(), template.into(), vars, perm).into_action()
+}
+
+/// Create directory
+pub struct CreateDir {
+    path: PathBuf,
+    perm: PathPermissions,
+}
+
+impl CreateDir {
+    const NAME: &'static str = "CreateDir";
+
+    pub fn new(path: PathBuf, perm: PathPermissions) -> Self {
+        Self { path, perm }
+    }
+}
+
+impl Action for CreateDir {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        if let Err(e) = std::fs::create_dir(&path) {
+            if e.kind() != ErrorKind::AlreadyExists {
+                // error creating new directory, and directory not exists yet
+                return fail(format!("can't create directory: {e}"));
+            }
+        }
+        if self.perm.apply(&path).is_some() {
+            (ActionResult::Ok, None)
+        } else {
+            fail("can't set permissions")
+        }
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        if !path.try_exists().ok()? {
+            // parents are never created (run fails if they are missing), so
+            // directory itself is the only one to remove
+            Some(remove_dir(&path))
+        } else if path.is_dir() {
+            let perm = PathPermissions::of(&path)?;
+            Some(SetPathPermissions::new(path, perm).into_action())
+        } else {
+            None
+        }
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [CreateDir]
+pub fn create_dir<DirPath>(path: DirPath) -> Box<dyn Action>
+where
+    DirPath: Into<PathBuf>,
+{
+    CreateDir::new(path.into(), PathPermissions::default()).into_action()
+}
+
+/// init [CreateDir] with custom permissions for created directory
+pub fn create_dir_perm<DirPath>(path: DirPath, perm: PathPermissions) -> Box<dyn Action>
+where
+    DirPath: Into<PathBuf>,
+{
+    CreateDir::new(path.into(), perm).into_action()
+}
+
+/// Removes empty directory, succeeds if directory does not exist
+pub struct RemoveDir {
+    path: PathBuf,
+}
+
+impl RemoveDir {
+    const NAME: &'static str = "RemoveDir";
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Action for RemoveDir {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        match std::fs::remove_dir(&path) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(e) if e.kind() == ErrorKind::NotFound => (ActionResult::Ok, None),
+            Err(e) => fail(format!("can't remove directory: {e}")),
+        }
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [RemoveDir]
+pub fn remove_dir<DirPath>(path: DirPath) -> Box<dyn Action>
+where
+    DirPath: Into<PathBuf>,
+{
+    RemoveDir::new(path.into()).into_action()
+}
+
+/// Set custom permissions for path (file or directory)
+pub struct SetPathPermissions {
+    path: PathBuf,
+    perm: PathPermissions,
+}
+
+impl SetPathPermissions {
+    const NAME: &'static str = "SetPathPermissions";
+
+    pub fn new(path: PathBuf, perm: PathPermissions) -> Self {
+        Self { path, perm }
+    }
+}
+
+impl Action for SetPathPermissions {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        if self.perm.apply(&path).is_some() {
+            (ActionResult::Ok, None)
+        } else {
+            fail("can't set permissions")
+        }
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        let perm = PathPermissions::of(&path)?;
+        Some(SetPathPermissions::new(path, perm).into_action())
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [SetPathPermissions] with access_mode and user, group equal to owner
+pub fn set_perm<FilePath, Name>(path: FilePath, access_mode: u32, owner: Name) -> Box<dyn Action>
+where
+    FilePath: Into<PathBuf>,
+    Name: Into<String>,
+{
+    SetPathPermissions::new(path.into(), perm(access_mode, owner)).into_action()
+}
+
+/// init [SetPathPermissions]
+pub fn set_perm_full<FilePath, Name>(
+    path: FilePath,
+    access_mode: u32,
+    user: Name,
+    group: Name,
+) -> Box<dyn Action>
+where
+    FilePath: Into<PathBuf>,
+    Name: Into<String>,
+{
+    SetPathPermissions::new(
+        path.into(),
+        PathPermissions::default()
+            .access(access_mode)
+            .user(user)
+            .group(group),
+    )
+    .into_action()
+}
+
+/// Replaces provided pattern in file with new data exactly once, will fail if
+/// file contains pattern multiple times or no pattern at all
+pub struct ReplaceInFileOnce {
+    path: PathBuf,
+    pattern: Pattern,
+    replacement: Vec<u8>,
+}
+
+impl ReplaceInFileOnce {
+    const NAME: &'static str = "ReplaceInFileOnce";
+
+    pub fn new(path: PathBuf, pattern: Pattern, replacement: Vec<u8>) -> Self {
+        Self {
+            path,
+            pattern,
+            replacement,
+        }
+    }
+}
+
+impl Action for ReplaceInFileOnce {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) => return fail(format!("can't read file: {e}")),
+        };
+        let Some(new_content) = self.pattern.replace_once(&content, &self.replacement) else {
+            return fail(self.pattern.explain_not_once(&content));
+        };
+        rewrite_file(&path, &new_content)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [ReplaceInFileOnce]
+pub fn replace_in_file_once<TPath, TPattern, TData>(
+    path: TPath,
+    pattern: TPattern,
+    replacement: TData,
+) -> Box<dyn Action>
+where
+    TPath: Into<PathBuf>,
+    TPattern: Into<Pattern>,
+    TData: Into<Vec<u8>>,
+{
+    ReplaceInFileOnce::new(path.into(), pattern.into(), replacement.into()).into_action()
+}
+
+/// Ensures that file has line: replaces first line matching pattern with it
+/// (other matching lines are removed) or inserts it at position if no line
+/// matches, file is not changed if it already has line (see
+/// [lines::ensure_line])
+pub struct EnsureLine {
+    path: PathBuf,
+    line: Vec<u8>,
+    pattern: Pattern,
+    position: LinePosition,
+}
+
+impl EnsureLine {
+    const NAME: &'static str = "EnsureLine";
+
+    pub fn new(path: PathBuf, line: Vec<u8>, pattern: Pattern, position: LinePosition) -> Self {
+        Self {
+            path,
+            line,
+            pattern,
+            position,
+        }
+    }
+}
+
+impl Action for EnsureLine {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) => return fail(format!("can't read file: {e}")),
+        };
+        let new_content = lines::ensure_line(&content, &self.line, &self.pattern, &self.position);
+        if new_content == content {
+            return (ActionResult::Ok, None);
+        }
+        rewrite_file(&path, &new_content)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
+    }
+
+    fn into_action(self) -> Box<dyn Action> {
+        Box::new(self)
+    }
+}
+
+/// init [EnsureLine], `line` is text of line without newline
+pub fn ensure_line<TPath, TLine, TPattern>(
+    path: TPath,
+    line: TLine,
+    pattern: TPattern,
+    position: LinePosition,
+) -> Box<dyn Action>
+where
+    TPath: Into<PathBuf>,
+    TLine: Into<Vec<u8>>,
+    TPattern: Into<Pattern>,
+{
+    EnsureLine::new(path.into(), line.into(), pattern.into(), position).into_action()
 }
 
-/// Create directory
-pub struct CreateDir {
+/// Removes all lines matching pattern from file, missing file has no lines
+pub struct EnsureLineAbsent {
     path: PathBuf,
-    perm: PathPermissions,
+    pattern: Pattern,
 }
 
-impl CreateDir {
-    const NAME: &'static str = "CreateDir";
+impl EnsureLineAbsent {
+    const NAME: &'static str = "EnsureLineAbsent";
 
-    pub fn new(path: PathBuf, perm: PathPermissions) -> Self {
-        Self { path, perm }
+    pub fn new(path: PathBuf, pattern: Pattern) -> Self {
+        Self { path, pattern }
     }
 }
 
-impl Action for CreateDir {
+impl Action for EnsureLineAbsent {
     fn name(&self) -> &str {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        if let Err(e) = std::fs::create_dir(&self.path) {
-            if e.kind() != ErrorKind::AlreadyExists {
-                // error creating new directory, and directory not exists yet
-                return ActionResult::Fail;
-            }
-        }
-        if self.perm.apply(&self.path).is_some() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return (ActionResult::Ok, None),
+            Err(e) => return fail(format!("can't read file: {e}")),
+        };
+        let new_content = lines::remove_lines(&content, &self.pattern);
+        if new_content == content {
+            return (ActionResult::Ok, None);
         }
+        rewrite_file(&path, &new_content)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -475,47 +1338,57 @@ impl Action for CreateDir {
     }
 }
 
-/// init [CreateDir]
-pub fn create_dir<DirPath>(path: DirPath) -> Box<dyn Action>
-where
-    DirPath: Into<PathBuf>,
-{
-    CreateDir::new(path.into(), PathPermissions::default()).into_action()
-}
-
-/// init [CreateDir] with custom permissions for created directory
-pub fn create_dir_perm<DirPath>(path: DirPath, perm: PathPermissions) -> Box<dyn Action>
+/// init [EnsureLineAbsent]
+pub fn ensure_line_absent<TPath, TPattern>(path: TPath, pattern: TPattern) -> Box<dyn Action>
 where
-    DirPath: Into<PathBuf>,
+    TPath: Into<PathBuf>,
+    TPattern: Into<Pattern>,
 {
-    CreateDir::new(path.into(), perm).into_action()
+    EnsureLineAbsent::new(path.into(), pattern.into()).into_action()
 }
 
-/// Set custom permissions for path (file or directory)
-pub struct SetPathPermissions {
+/// Ensures that file has block of lines between `# BEGIN pass <id>` and
+/// `# END pass <id>` markers, existing block is updated in place, new one is
+/// appended (see [lines::ensure_block]). Missing file is created
+pub struct EnsureBlock {
     path: PathBuf,
-    perm: PathPermissions,
+    id: String,
+    block: Vec<u8>,
 }
 
-impl SetPathPermissions {
-    const NAME: &'static str = "SetPathPermissions";
+impl EnsureBlock {
+    const NAME: &'static str = "EnsureBlock";
 
-    pub fn new(path: PathBuf, perm: PathPermissions) -> Self {
-        Self { path, perm }
+    pub fn new(path: PathBuf, id: String, block: Vec<u8>) -> Self {
+        Self { path, id, block }
     }
 }
 
-impl Action for SetPathPermissions {
+impl Action for EnsureBlock {
     fn name(&self) -> &str {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        if self.perm.apply(&self.path).is_some() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return fail(format!("can't read file: {e}")),
+        };
+        let new_content = match lines::ensure_block(&content, &self.id, &self.block) {
+            Ok(new_content) => new_content,
+            Err(reason) => return fail(reason),
+        };
+        if new_content == content && path.exists() {
+            return (ActionResult::Ok, None);
         }
+        rewrite_file(&path, &new_content)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -523,73 +1396,56 @@ impl Action for SetPathPermissions {
     }
 }
 
-/// init [SetPathPermissions] with access_mode and user, group equal to owner
-pub fn set_perm<FilePath, Name>(path: FilePath, access_mode: u32, owner: Name) -> Box<dyn Action>
-where
-    FilePath: Into<PathBuf>,
-    Name: Into<String>,
-{
-    SetPathPermissions::new(path.into(), perm(access_mode, owner)).into_action()
-}
-
-/// init [SetPathPermissions]
-pub fn set_perm_full<FilePath, Name>(
-    path: FilePath,
-    access_mode: u32,
-    user: Name,
-    group: Name,
-) -> Box<dyn Action>
+/// init [EnsureBlock]
+pub fn ensure_block<TPath, TId, TBlock>(path: TPath, id: TId, block: TBlock) -> Box<dyn Action>
 where
-    FilePath: Into<PathBuf>,
-    Name: Into<String>,
+    TPath: Into<PathBuf>,
+    TId: Into<String>,
+    TBlock: Into<Vec<u8>>,
 {
-    SetPathPermissions::new(
-        path.into(),
-        PathPermissions::default()
-            .access(access_mode)
-            .user(user)
-            .group(group),
-    )
-    .into_action()
+    EnsureBlock::new(path.into(), id.into(), block.into()).into_action()
 }
 
-/// Replaces provided pattern in file with new data exactly once, will fail if
-/// file contains pattern multiple times or no pattern at all
-pub struct ReplaceInFileOnce {
+/// Removes block with provided id including its markers (see [EnsureBlock]),
+/// missing file has no blocks
+pub struct RemoveBlock {
     path: PathBuf,
-    pattern: Pattern,
-    replacement: Vec<u8>,
+    id: String,
 }
 
-impl ReplaceInFileOnce {
-    const NAME: &'static str = "ReplaceInFileOnce";
+impl RemoveBlock {
+    const NAME: &'static str = "RemoveBlock";
 
-    pub fn new(path: PathBuf, pattern: Pattern, replacement: Vec<u8>) -> Self {
-        Self {
-            path,
-            pattern,
-            replacement,
-        }
+    pub fn new(path: PathBuf, id: String) -> Self {
+        Self { path, id }
     }
 }
 
-impl Action for ReplaceInFileOnce {
+impl Action for RemoveBlock {
     fn name(&self) -> &str {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        let Some(new_content) = std::fs::read(&self.path)
-            .ok()
-            .and_then(|c| self.pattern.replace_once(&c, &self.replacement))
-        else {
-            return ActionResult::Fail;
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return (ActionResult::Ok, None),
+            Err(e) => return fail(format!("can't read file: {e}")),
         };
-        if std::fs::write(&self.path, new_content).is_err() {
-            ActionResult::Fail
-        } else {
-            ActionResult::Ok
+        let new_content = match lines::remove_block(&content, &self.id) {
+            Ok(new_content) => new_content,
+            Err(reason) => return fail(reason),
+        };
+        if new_content == content {
+            return (ActionResult::Ok, None);
         }
+        rewrite_file(&path, &new_content)
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        restore_file(&path)
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -597,18 +1453,13 @@ impl Action for ReplaceInFileOnce {
     }
 }
 
-/// init [ReplaceInFileOnce]
-pub fn replace_in_file_once<TPath, TPattern, TData>(
-    path: TPath,
-    pattern: TPattern,
-    replacement: TData,
-) -> Box<dyn Action>
+/// init [RemoveBlock]
+pub fn remove_block<TPath, TId>(path: TPath, id: TId) -> Box<dyn Action>
 where
     TPath: Into<PathBuf>,
-    TPattern: Into<Pattern>,
-    TData: Into<Vec<u8>>,
+    TId: Into<String>,
 {
-    ReplaceInFileOnce::new(path.into(), pattern.into(), replacement.into()).into_action()
+    RemoveBlock::new(path.into(), id.into()).into_action()
 }
 
 /// Rename file or directory
@@ -630,11 +1481,27 @@ impl Action for RenamePath {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        if std::fs::rename(&self.path, &self.new_path).is_ok() {
-            ActionResult::Ok
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let new_path = ctx.resolve(&self.new_path);
+        // renaming over existing file loses it
+        if let Err(reason) = backup_file(&path).and_then(|_| backup_file(&new_path)) {
+            return fail(reason);
+        }
+        match std::fs::rename(&path, &new_path) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(e) => fail(format!("can't rename path: {e}")),
+        }
+    }
+
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        let path = ctx.resolve(&self.path);
+        let new_path = ctx.resolve(&self.new_path);
+        // renaming over existing path loses it, can't be undone
+        if new_path.try_exists().ok()? {
+            None
         } else {
-            ActionResult::Fail
+            Some(RenamePath::new(new_path, path).into_action())
         }
     }
 
@@ -679,7 +1546,7 @@ impl Action for ServiceCommand {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
+    fn run_in(&self, _ctx: &ExecContext) -> (ActionResult, Option<String>) {
         let command = match self.command {
             ServiceCommands::Start => "start",
             ServiceCommands::Stop => "stop",
@@ -688,12 +1555,7 @@ impl Action for ServiceCommand {
             ServiceCommands::Enable => "enable",
             ServiceCommands::Disable => "disable",
         };
-        let result = run(&norm_cmd(["systemctl", command, &self.service]));
-        if result.ok() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
-        }
+        explain_process(run(&norm_cmd(["systemctl", command, &self.service])))
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -767,6 +1629,15 @@ impl CopyFile {
             new_name,
         }
     }
+
+    fn target_path(&self, ctx: &ExecContext) -> Option<PathBuf> {
+        let target_dir = ctx.resolve(&self.target_dir);
+        if let Some(name) = &self.new_name {
+            Some(target_dir.join(name))
+        } else {
+            Some(target_dir.join(self.file_path.file_name()?))
+        }
+    }
 }
 
 impl Action for CopyFile {
@@ -774,22 +1645,21 @@ impl Action for CopyFile {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        let target_path = if let Some(name) = &self.new_name {
-            self.target_dir.join(name)
-        } else {
-            let Some(name) = self.file_path.file_name() else {
-                return ActionResult::Fail;
-            };
-            self.target_dir.join(name)
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let file_path = ctx.resolve(&self.file_path);
+        let Some(target_path) = self.target_path(ctx) else {
+            return fail("file path has no file name");
         };
-        if std::fs::copy(&self.file_path, target_path).is_ok() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
+        match std::fs::copy(&file_path, target_path) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(e) => fail(format!("can't copy file: {e}")),
         }
     }
 
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        restore_file(&self.target_path(ctx)?)
+    }
+
     fn into_action(self) -> Box<dyn Action> {
         Box::new(self)
     }
@@ -818,7 +1688,8 @@ where
     CopyFile::new(file_path.into(), target_dir.into(), Some(new_name.into())).into_action()
 }
 
-/// Changes current working directory to the provided one
+/// Changes current working directory of process to the provided one (use
+/// [dir](crate::dir_context::dir) to change it only for some actions or checks)
 pub struct SetDir(PathBuf);
 
 impl SetDir {
@@ -834,11 +1705,10 @@ impl Action for SetDir {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        if std::env::set_current_dir(&self.0).is_ok() {
-            ActionResult::Ok
-        } else {
-            ActionResult::Fail
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        match with_dir_lock(|| std::env::set_current_dir(ctx.resolve(&self.0))) {
+            Ok(_) => (ActionResult::Ok, None),
+            Err(e) => fail(format!("can't change directory: {e}")),
         }
     }
 
@@ -856,14 +1726,28 @@ where
 }
 
 /// Wait until provided file appears on disk (can be used for some type of
-/// synchronization)
-pub struct WaitForFile(PathBuf);
+/// synchronization), fails if file not appeared during optional timeout (or
+/// before deadline set with [with_deadline](crate::process::with_deadline))
+pub struct WaitForFile {
+    path: PathBuf,
+    timeout: Option<Duration>,
+}
 
 impl WaitForFile {
     const NAME: &'static str = "WaitForFile";
 
     pub fn new(path: PathBuf) -> Self {
-        Self(path)
+        Self {
+            path,
+            timeout: None,
+        }
+    }
+
+    pub fn with_timeout(path: PathBuf, timeout: Duration) -> Self {
+        Self {
+            path,
+            timeout: Some(timeout),
+        }
     }
 }
 
@@ -872,12 +1756,26 @@ impl Action for WaitForFile {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        while !self.0.is_file() {
-            std::thread::sleep(std::time::Duration::from_secs(1));
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let timeout_at = self.timeout.map(|t| Instant::now() + t);
+        let deadline = match (timeout_at, deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        while !path.is_file() {
+            let mut delay = Duration::from_secs(1);
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return fail("file not appeared before timeout");
+                }
+                delay = delay.min(deadline - now);
+            }
+            std::thread::sleep(delay);
         }
         std::thread::sleep(std::time::Duration::from_secs(1));
-        ActionResult::Ok
+        (ActionResult::Ok, None)
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -893,18 +1791,31 @@ where
     WaitForFile::new(path.into()).into_action()
 }
 
-/// implements [Action] for tuple with name and function
+/// inits [WaitForFile] which fails if file not appeared during `timeout`
+pub fn wait_for_file_timeout<P>(path: P, timeout: Duration) -> Box<dyn Action>
+where
+    P: Into<PathBuf>,
+{
+    WaitForFile::with_timeout(path.into(), timeout).into_action()
+}
+
+/// implements [Action] for tuple with name and function, function does not
+/// receive [ExecContext], so current directory of process is changed to
+/// directory of context while it runs (see [in_dir])
 impl<N, F> Action for (N, F)
 where
-    N: AsRef<str> + 'static,
-    F: Fn() -> ActionResult + 'static,
+    N: AsRef<str> + Send + Sync + 'static,
+    F: Fn() -> ActionResult + Send + Sync + 'static,
 {
     fn name(&self) -> &str {
         self.0.as_ref()
     }
 
-    fn run(&self) -> ActionResult {
-        self.1()
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        match in_dir(ctx, || self.1()) {
+            Ok(result) => (result, None),
+            Err(reason) => fail(reason),
+        }
     }
 
     fn into_action(self) -> Box<dyn Action> {
@@ -915,8 +1826,17 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::pattern::re;
-    use std::time::{Duration, Instant};
+    use crate::{
+        pattern::re,
+        template::{template, vars},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
 
     const NOT_A_FILE: &str = "/tmp/not-a-pass-test-file-5555555555";
 
@@ -948,6 +1868,53 @@ mod test {
         assert_eq!(many([]).run(), ActionResult::Ok);
         assert_eq!(many([always_ok(), always_ok()]).run(), ActionResult::Ok);
         assert_eq!(many([always_ok(), always_fail()]).run(), ActionResult::Fail);
+        assert_eq!(
+            many([always_ok(), command(["false"])]).run_explained(),
+            (
+                ActionResult::Fail,
+                Some("`Command` failed: process exited with error code 1".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn test_retry() {
+        assert_eq!(Backoff::None.delay(3), Duration::ZERO);
+        assert_eq!(
+            Backoff::Fixed(Duration::from_secs(2)).delay(3),
+            Duration::from_secs(2)
+        );
+        let exp = Backoff::Exponential(Duration::from_millis(100));
+        assert_eq!(exp.delay(1), Duration::from_millis(100));
+        assert_eq!(exp.delay(3), Duration::from_millis(400));
+        // no overflow for big number of retries
+        assert!(exp.delay(1000) > Duration::from_secs(3600));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_copy = runs.clone();
+        let fails_twice = ("fails twice", move || {
+            if runs_copy.fetch_add(1, Ordering::SeqCst) + 1 > 2 {
+                ActionResult::Ok
+            } else {
+                ActionResult::Fail
+            }
+        })
+            .into_action();
+        let a = retry(fails_twice, 3, Backoff::Fixed(Duration::from_millis(1)));
+        assert_eq!(a.run(), ActionResult::Ok);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            retry(command(["false"]), 2, Backoff::None).run_explained(),
+            (
+                ActionResult::Fail,
+                Some(
+                    "failed 2 times\n\
+                     attempt 1: process exited with error code 1\n\
+                     attempt 2: process exited with error code 1"
+                        .to_owned()
+                )
+            )
+        );
+        assert_eq!(retry(always_ok(), 0, Backoff::None).run(), ActionResult::Ok);
     }
 
     #[test]
@@ -958,6 +1925,42 @@ mod test {
             command(["random-incorrect-command-aaabbb222"]).run(),
             ActionResult::Fail
         );
+        assert_eq!(
+            command(["echo", "1"]).run_explained(),
+            (ActionResult::Ok, None)
+        );
+        assert_eq!(
+            command(["sh", "-c", "echo failed >&2; exit 3"]).run_explained(),
+            (
+                ActionResult::Fail,
+                Some("process exited with error code 3, stderr:\nfailed".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn test_command_output_into() {
+        crate::facts::scope(|| {
+            assert_eq!(
+                command_output_into("greeting", ["echo", " hello "]).run(),
+                ActionResult::Ok
+            );
+            assert_eq!(crate::facts::get_fact("greeting"), Some("hello".to_owned()));
+            assert_eq!(
+                command_output_into("greeting", ["false"]).run(),
+                ActionResult::Fail
+            );
+            let greeting = lazy("check greeting", |facts| {
+                if facts.get("greeting") == Some("hello") {
+                    always_ok()
+                } else {
+                    always_fail()
+                }
+            });
+            assert_eq!(greeting.run(), ActionResult::Ok);
+            crate::facts::set_fact("greeting", "bye");
+            assert_eq!(greeting.run(), ActionResult::Fail);
+        });
     }
 
     #[test]
@@ -988,6 +1991,137 @@ mod test {
         // use manual test test_write_file
     }
 
+    #[test]
+    fn test_write_atomic() {
+        let d: PathBuf = "/tmp/pass-test-dir-111222333-test_write_atomic".into();
+        std::fs::create_dir(&d).unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        // access mode of replaced file is kept
+        let p = d.join("file");
+        std::fs::write(&p, "aaa").unwrap();
+        std::fs::set_permissions(&p, std::fs::Permissions::from_mode(0o640)).unwrap();
+        assert_eq!(write_file(&p, "111").run(), ActionResult::Ok);
+        assert_eq!(std::fs::read(&p).unwrap(), "111".as_bytes());
+        assert_eq!(mode(&p), 0o640);
+        let perm = PathPermissions::default().access(0o600);
+        assert_eq!(write_file_perm(&p, "222", perm).run(), ActionResult::Ok);
+        assert_eq!(mode(&p), 0o600);
+        // symlink is kept, file it points to is replaced
+        let link = d.join("link");
+        std::os::unix::fs::symlink(&p, &link).unwrap();
+        assert_eq!(
+            replace_in_file_once(&link, "2", "3").run(),
+            ActionResult::Fail
+        );
+        assert_eq!(
+            replace_in_file_once(&link, "222", "333").run(),
+            ActionResult::Ok
+        );
+        assert!(link.is_symlink());
+        assert_eq!(std::fs::read(&p).unwrap(), "333".as_bytes());
+        assert_eq!(mode(&p), 0o600);
+        // no temporary files left
+        let mut names: Vec<_> = std::fs::read_dir(&d)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["file", "link"]);
+        // dangling symlink creates its target
+        let dangling = d.join("dangling");
+        std::os::unix::fs::symlink("created", &dangling).unwrap();
+        assert_eq!(write_file(&dangling, "444").run(), ActionResult::Ok);
+        assert!(dangling.is_symlink());
+        assert_eq!(std::fs::read(d.join("created")).unwrap(), "444".as_bytes());
+        std::fs::remove_file(dangling).unwrap();
+        std::fs::remove_file(d.join("created")).unwrap();
+        let looped = d.join("looped");
+        std::os::unix::fs::symlink("looped", &looped).unwrap();
+        assert_eq!(write_file(&looped, "").run(), ActionResult::Fail);
+        std::fs::remove_file(looped).unwrap();
+        assert_eq!(
+            write_file(d.join("missing/file"), "").run_explained().0,
+            ActionResult::Fail
+        );
+        std::fs::remove_file(link).unwrap();
+        std::fs::remove_file(p).unwrap();
+        std::fs::remove_dir(d).unwrap();
+    }
+
+    #[test]
+    fn test_write_template() {
+        let p = "/tmp/pass-test-file-111222333-test_write_template";
+        let vars = vars().var("name", "pass");
+        assert_eq!(
+            write_template(p, "name={{ name }}\n", vars.clone()).run(),
+            ActionResult::Ok
+        );
+        assert_eq!(std::fs::read(p).unwrap(), "name=pass\n".as_bytes());
+        assert_eq!(
+            write_template(p, template("{{ missing }}").strict(), vars).run_explained(),
+            (
+                ActionResult::Fail,
+                Some("can't render template: line 1: undefined variable `missing`".to_owned())
+            )
+        );
+        assert_eq!(std::fs::read(p).unwrap(), "name=pass\n".as_bytes());
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_ensure_line() {
+        let p = create_test_file("test_ensure_line");
+        std::fs::write(&p, "Port 22\n#PermitRootLogin yes\n").unwrap();
+        let ensure = || {
+            ensure_line(
+                &p,
+                "PermitRootLogin no",
+                re("^#?PermitRootLogin "),
+                LinePosition::End,
+            )
+        };
+        assert_eq!(ensure().run(), ActionResult::Ok);
+        assert_eq!(std::fs::read(&p).unwrap(), b"Port 22\nPermitRootLogin no\n");
+        assert_eq!(ensure().run(), ActionResult::Ok);
+        assert_eq!(std::fs::read(&p).unwrap(), b"Port 22\nPermitRootLogin no\n");
+        assert_eq!(ensure_line_absent(&p, re("^Port ")).run(), ActionResult::Ok);
+        assert_eq!(std::fs::read(&p).unwrap(), b"PermitRootLogin no\n");
+        std::fs::remove_file(&p).unwrap();
+        assert_eq!(ensure_line_absent(&p, re("^Port ")).run(), ActionResult::Ok);
+        assert_eq!(ensure().run(), ActionResult::Fail);
+    }
+
+    #[test]
+    fn test_ensure_block() {
+        let p = create_test_file("test_ensure_block");
+        std::fs::remove_file(&p).unwrap();
+        let with_block = "# BEGIN pass db\n10.0.0.2 db\n# END pass db\n";
+        // missing file is created
+        assert_eq!(
+            ensure_block(&p, "db", "10.0.0.2 db").run(),
+            ActionResult::Ok
+        );
+        assert_eq!(std::fs::read(&p).unwrap(), with_block.as_bytes());
+        assert_eq!(
+            ensure_block(&p, "db", "10.0.0.2 db").run(),
+            ActionResult::Ok
+        );
+        assert_eq!(std::fs::read(&p).unwrap(), with_block.as_bytes());
+        assert_eq!(remove_block(&p, "db").run(), ActionResult::Ok);
+        assert_eq!(std::fs::read(&p).unwrap(), b"");
+        std::fs::write(&p, "# END pass db\n").unwrap();
+        assert_eq!(
+            ensure_block(&p, "db", "").run_explained(),
+            (
+                ActionResult::Fail,
+                Some("begin marker of block `db` not found".to_owned())
+            )
+        );
+        assert_eq!(remove_block(&p, "db").run(), ActionResult::Fail);
+        std::fs::remove_file(&p).unwrap();
+        assert_eq!(remove_block(&p, "db").run(), ActionResult::Ok);
+    }
+
     #[test]
     fn test_create_dir() {
         assert_eq!(
@@ -1028,6 +2162,17 @@ mod test {
                 replace_in_file_once(&p, "111", "222").run(),
                 ActionResult::Fail
             );
+            assert_eq!(
+                replace_in_file_once(&p, "a", "11").run_explained(),
+                (
+                    ActionResult::Fail,
+                    Some("pattern matched 3 times".to_owned())
+                )
+            );
+            assert_eq!(
+                replace_in_file_once(&p, "111", "222").run_explained(),
+                (ActionResult::Fail, Some("pattern not found".to_owned()))
+            );
             assert_eq!(replace_in_file_once(&p, "", "").run(), ActionResult::Fail);
             assert_eq!(std::fs::read(&p).unwrap(), "aaabbbccc".as_bytes());
             assert_eq!(
@@ -1139,5 +2284,85 @@ mod test {
         let time_b = Instant::now();
         std::fs::remove_file(path).unwrap();
         assert!((time_b - time_a).as_secs_f32() >= 4.0);
+        let time_a = Instant::now();
+        assert_eq!(
+            wait_for_file_timeout(NOT_A_FILE, Duration::from_millis(300)).run(),
+            ActionResult::Fail
+        );
+        assert!(time_a.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let time_a = Instant::now();
+        assert_eq!(
+            timeout(Duration::from_millis(200), command(["sleep", "10"])).run(),
+            ActionResult::Fail
+        );
+        assert!(time_a.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            timeout(Duration::from_secs(5), command(["true"])).run(),
+            ActionResult::Ok
+        );
+        assert_eq!(
+            timeout(
+                Duration::from_millis(200),
+                wait_for_file("/tmp/not-a-pass-test-file-5555555555-timeout")
+            )
+            .run(),
+            ActionResult::Fail
+        );
+    }
+
+    #[test]
+    fn test_undo() {
+        assert!(always_ok().undo().is_none());
+        assert!(command(["true"]).undo().is_none());
+        {
+            let p = create_test_file("test_undo_write_file");
+            let a = write_file(&p, "111");
+            let undo = a.undo().unwrap();
+            assert_eq!(a.run(), ActionResult::Ok);
+            assert_eq!(undo.run(), ActionResult::Ok);
+            assert_eq!(std::fs::read(&p).unwrap(), "aaabbbccc".as_bytes());
+            let a = delete_file(&p);
+            let undo = a.undo().unwrap();
+            assert_eq!(a.run(), ActionResult::Ok);
+            assert_eq!(undo.run(), ActionResult::Ok);
+            assert_eq!(std::fs::read(&p).unwrap(), "aaabbbccc".as_bytes());
+            // undo can be run more than once until it is dropped
+            assert_eq!(undo.run(), ActionResult::Ok);
+            std::fs::remove_file(&p).unwrap();
+        }
+        {
+            let d = "/tmp/pass-test-dir-111222333-test_undo";
+            let p = format!("{d}/file");
+            let a = many([create_dir(d), write_file(&p, "111")]);
+            let undo = a.undo().unwrap();
+            assert_eq!(a.run(), ActionResult::Ok);
+            assert!(PathBuf::from(&p).is_file());
+            assert_eq!(undo.run(), ActionResult::Ok);
+            assert!(!PathBuf::from(d).exists());
+            std::fs::create_dir(d).unwrap();
+            let a = create_dir(format!("{d}/inner"));
+            let undo = a.undo().unwrap();
+            assert_eq!(a.run(), ActionResult::Ok);
+            assert_eq!(undo.run(), ActionResult::Ok);
+            assert!(!PathBuf::from(format!("{d}/inner")).exists());
+            // existing parent is kept
+            assert!(PathBuf::from(d).is_dir());
+            std::fs::remove_dir(d).unwrap();
+        }
+        {
+            let path1 = "/tmp/pass-test-file-111222333-test_undo_rename_1";
+            let path2 = "/tmp/pass-test-file-111222333-test_undo_rename_2";
+            std::fs::write(path1, "path1").unwrap();
+            let a = rename_path(path1, path2);
+            let undo = a.undo().unwrap();
+            assert_eq!(a.run(), ActionResult::Ok);
+            assert_eq!(undo.run(), ActionResult::Ok);
+            assert!(PathBuf::from(path1).is_file());
+            std::fs::remove_file(path1).unwrap();
+        }
     }
 }
@@ -0,0 +1,281 @@
+//! Backups of files changed by actions, allows to restore content of files
+//! from any run of playbook (see [Playbook::with_backups](crate::Playbook::with_backups))
+
+use std::{
+    cell::RefCell,
+    fs::DirBuilder,
+    io::ErrorKind,
+    os::unix::fs::{chown, DirBuilderExt, MetadataExt},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::actions::{write_atomic, PathPermissions};
+
+/// Directory where [Playbook](crate::Playbook) saves backups, each playbook
+/// has its own subdirectory with directory for each run
+pub const BACKUP_DIR: &str = "/srv/pass/backups";
+
+thread_local! {
+    static BACKUP: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` saving backups of files before they are changed by actions into
+/// provided directory
+pub fn with_backup_dir<P, F, R>(dir: P, f: F) -> R
+where
+    P: Into<PathBuf>,
+    F: FnOnce() -> R,
+{
+    let outer = BACKUP.with(|b| b.replace(Some(dir.into())));
+    let result = f();
+    BACKUP.with(|b| *b.borrow_mut() = outer);
+    result
+}
+
+/// Directory where backups are saved, [None] outside of [with_backup_dir]
+pub fn backup_dir() -> Option<PathBuf> {
+    BACKUP.with(|b| b.borrow().clone())
+}
+
+/// Copies file into backup directory (see [with_backup_dir]) keeping its
+/// absolute path inside of it. Nothing is done if backups are not enabled,
+/// path is not a file or file is already backed up, so backup keeps content
+/// before first change
+pub(crate) fn backup_file(path: &Path) -> Result<(), String> {
+    let Some(dir) = backup_dir() else {
+        return Ok(());
+    };
+    let metadata = match std::fs::metadata(path) {
+        Ok(m) if m.is_file() => m,
+        _ => return Ok(()),
+    };
+    let path = std::path::absolute(path).map_err(|e| format!("can't backup file: {e}"))?;
+    let target = backup_path(&dir, &normalize(&path))?;
+    if target.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = target.parent() {
+        // backups can contain secrets
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .map_err(|e| format!("can't create backup directory: {e}"))?;
+    }
+    std::fs::copy(&path, &target).map_err(|e| format!("can't backup file: {e}"))?;
+    // owners are restored together with content, changing them requires
+    // privileges, without them backup is owned by current user
+    let _ = chown(&target, Some(metadata.uid()), Some(metadata.gid()));
+    Ok(())
+}
+
+/// Removes `.` and `..` from absolute path without accessing file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normal.push(name),
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    normal
+}
+
+/// Path of backup of file inside of run directory, fails if it is outside of
+/// it
+fn backup_path(run_dir: &Path, path: &Path) -> Result<PathBuf, String> {
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(format!(
+            "backup of {} is outside of backup directory",
+            path.display()
+        ));
+    }
+    Ok(run_dir.join(relative))
+}
+
+/// Creates new run directory inside of `dir`, named by current time (UTC),
+/// concurrent runs always get different directories
+pub(crate) fn new_run_dir(dir: &Path) -> Result<PathBuf, String> {
+    let failed = |e: std::io::Error| format!("can't create backup directory: {e}");
+    // backups can contain secrets
+    let mut builder = DirBuilder::new();
+    builder.mode(0o700);
+    builder.recursive(true).create(dir).map_err(failed)?;
+    builder.recursive(false);
+    let name = run_name(SystemTime::now());
+    let mut run_dir = dir.join(&name);
+    let mut n = 1;
+    loop {
+        match builder.create(&run_dir) {
+            Ok(_) => return Ok(run_dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                n += 1;
+                run_dir = dir.join(format!("{name}-{n}"));
+            }
+            Err(e) => return Err(failed(e)),
+        }
+    }
+}
+
+/// Formats time as `YYYYMMDD-HHMMSS`
+fn run_name(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // civil date from days since epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Names of runs with backups saved in directory, oldest first
+pub fn runs(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut runs: Vec<String> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    runs.sort();
+    runs
+}
+
+/// Original paths of files backed up during run
+pub fn files(run_dir: &Path) -> Vec<PathBuf> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            // symlinks are not followed, backups are regular files
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => walk(&entry.path(), files),
+                Ok(t) if t.is_file() => files.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+    let mut files = vec![];
+    walk(run_dir, &mut files);
+    let mut files: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|f| f.strip_prefix(run_dir).ok())
+        .map(|f| Path::new("/").join(f))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Restores content, access mode and owners of all files backed up during
+/// run, returns restored paths
+pub fn restore(run_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    if !run_dir.is_dir() {
+        return Err(format!("no backups in {}", run_dir.display()));
+    }
+    let files = files(run_dir);
+    for file in &files {
+        let failed = |reason: String| format!("can't restore {}: {reason}", file.display());
+        let backup = backup_path(run_dir, file).map_err(failed)?;
+        let content = std::fs::read(&backup).map_err(|e| failed(format!("{e}")))?;
+        let perm = PathPermissions::of(&backup).unwrap_or_default();
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| failed(format!("{e}")))?;
+        }
+        write_atomic(file, &content, &perm).map_err(failed)?;
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        actions::{delete_file, rename_path, replace_in_file_once, write_file},
+        interfaces::ActionResult,
+    };
+
+    #[test]
+    fn test_run_name() {
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        assert_eq!(run_name(time), "20231114-221320");
+        assert_eq!(run_name(UNIX_EPOCH), "19700101-000000");
+    }
+
+    #[test]
+    fn test_backup() {
+        let root: PathBuf = "/tmp/pass-test-dir-111222333-test_backup".into();
+        let _ = std::fs::remove_dir_all(&root);
+        let files_dir = root.join("files");
+        std::fs::create_dir_all(&files_dir).unwrap();
+        let a = files_dir.join("a");
+        let b = files_dir.join("b");
+        std::fs::write(&a, "a1").unwrap();
+        std::fs::write(&b, "b1").unwrap();
+        let backups = root.join("backups");
+        let run_dir = new_run_dir(&backups).unwrap();
+        // run started at the same time gets another directory
+        let other_run_dir = new_run_dir(&backups).unwrap();
+        assert_ne!(run_dir, other_run_dir);
+        std::fs::remove_dir(other_run_dir).unwrap();
+        with_backup_dir(&run_dir, || {
+            assert_eq!(write_file(&a, "a2").run(), ActionResult::Ok);
+            // only content before first change is kept
+            assert_eq!(replace_in_file_once(&a, "a2", "a3").run(), ActionResult::Ok);
+            assert_eq!(delete_file(&b).run(), ActionResult::Ok);
+            // missing file is not backed up
+            assert_eq!(
+                rename_path(files_dir.join("c"), files_dir.join("d")).run(),
+                ActionResult::Fail
+            );
+        });
+        assert_eq!(backup_dir(), None);
+        // changes outside of context are not backed up
+        assert_eq!(write_file(files_dir.join("c"), "c").run(), ActionResult::Ok);
+        assert_eq!(
+            runs(&backups),
+            [run_dir.file_name().unwrap().to_str().unwrap()]
+        );
+        assert_eq!(files(&run_dir), [a.clone(), b.clone()]);
+        assert_eq!(restore(&run_dir).unwrap(), [a.clone(), b.clone()]);
+        assert_eq!(std::fs::read(&a).unwrap(), b"a1");
+        assert_eq!(std::fs::read(&b).unwrap(), b"b1");
+        assert!(restore(&backups.join("missing")).is_err());
+        // relative path can't escape run directory
+        assert_eq!(
+            normalize(Path::new("/srv/a/../../../b/./c")),
+            PathBuf::from("/b/c")
+        );
+        assert!(backup_path(&run_dir, Path::new("/../b")).is_err());
+        let c = files_dir.join("c");
+        let escaping = files_dir
+            .join("../".repeat(20))
+            .join(c.strip_prefix("/").unwrap());
+        with_backup_dir(&run_dir, || {
+            assert_eq!(write_file(&escaping, "c2").run(), ActionResult::Ok);
+        });
+        assert_eq!(files(&run_dir), [a.clone(), b.clone(), c.clone()]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
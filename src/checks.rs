@@ -1,10 +1,26 @@
 use crate::{
-    interfaces::Check,
+    dir_context::in_dir,
+    facts::{get_fact, with_facts, Facts},
+    interfaces::{Check, ExecContext},
+    lines::{self, LinePosition},
     pattern::Pattern,
-    process::{norm_cmd, run, ExitCode, ProcessOutput},
+    process::{run, run_spec, with_deadline, ExitCode, ProcessOutput, ProcessSpec},
+    template::{Template, Vars},
 };
 use nix::unistd::Uid;
-use std::{fs::OpenOptions, path::PathBuf};
+use std::{
+    fs::OpenOptions,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Negative result of [Check::yes_explained] with provided reason
+fn no<Reason>(reason: Reason) -> (bool, Option<String>)
+where
+    Reason: Into<String>,
+{
+    (false, Some(reason.into()))
+}
 
 /// Check which always `true`
 pub struct AlwaysYes;
@@ -18,8 +34,8 @@ impl Check for AlwaysYes {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        true
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
+        (true, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -44,8 +60,8 @@ impl Check for AlwaysNo {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        false
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
+        (false, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -75,8 +91,12 @@ impl Check for Named {
         &self.name
     }
 
-    fn yes(&self) -> bool {
-        self.check.yes()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        self.check.yes_in(ctx)
+    }
+
+    fn kind(&self) -> &str {
+        self.check.kind()
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -92,6 +112,135 @@ where
     Named::new(name.into(), check).into_check()
 }
 
+/// Check which is *no* if another check runs longer than provided time,
+/// external processes started by check are killed when time is out
+pub struct Timeout {
+    check: Box<dyn Check>,
+    timeout: Duration,
+}
+
+impl Timeout {
+    const NAME: &'static str = "Timeout";
+
+    pub fn new(timeout: Duration, check: Box<dyn Check>) -> Self {
+        Self { check, timeout }
+    }
+}
+
+impl Check for Timeout {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let deadline = Instant::now() + self.timeout;
+        let result = with_deadline(deadline, || self.check.yes_in(ctx));
+        if Instant::now() > deadline {
+            no(format!("timed out after {:?}", self.timeout))
+        } else {
+            result
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [Timeout]
+pub fn timeout(timeout: Duration, check: Box<dyn Check>) -> Box<dyn Check> {
+    Timeout::new(timeout, check).into_check()
+}
+
+/// Function building check from facts
+pub type BuildCheck = Box<dyn Fn(&Facts) -> Box<dyn Check> + Send + Sync>;
+
+/// Check which is built right before it is checked, using facts stored by
+/// actions (see [Lazy](crate::actions::Lazy))
+pub struct Lazy {
+    name: String,
+    build: BuildCheck,
+}
+
+impl Lazy {
+    pub fn new(name: String, build: BuildCheck) -> Self {
+        Self { name, build }
+    }
+}
+
+impl Check for Lazy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        with_facts(|facts| (self.build)(facts)).yes_in(ctx)
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [Lazy]
+pub fn lazy<Name, Build>(name: Name, build: Build) -> Box<dyn Check>
+where
+    Name: Into<String>,
+    Build: Fn(&Facts) -> Box<dyn Check> + Send + Sync + 'static,
+{
+    Lazy::new(name.into(), Box::new(build)).into_check()
+}
+
+/// Checks if fact is stored and optionally if it has provided value
+pub struct FactIs {
+    fact: String,
+    value: Option<String>,
+}
+
+impl FactIs {
+    const NAME: &'static str = "FactIs";
+
+    pub fn new(fact: String, value: Option<String>) -> Self {
+        Self { fact, value }
+    }
+}
+
+impl Check for FactIs {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
+        let yes = match (get_fact(&self.fact), &self.value) {
+            (Some(fact), Some(value)) => fact == *value,
+            (fact, None) => fact.is_some(),
+            (None, Some(_)) => false,
+        };
+        (yes, None)
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [FactIs], checks if fact is stored
+pub fn has_fact<Fact>(fact: Fact) -> Box<dyn Check>
+where
+    Fact: Into<String>,
+{
+    FactIs::new(fact.into(), None).into_check()
+}
+
+/// init [FactIs], checks if fact has provided value
+pub fn fact_is<Fact, Value>(fact: Fact, value: Value) -> Box<dyn Check>
+where
+    Fact: Into<String>,
+    Value: Into<String>,
+{
+    FactIs::new(fact.into(), Some(value.into())).into_check()
+}
+
 /// Checks if current user is root
 pub struct UserIsRoot;
 
@@ -104,8 +253,8 @@ impl Check for UserIsRoot {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        Uid::effective().is_root()
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
+        (Uid::effective().is_root(), None)
     }
     fn into_check(self) -> Box<dyn Check> {
         Box::new(self)
@@ -135,12 +284,10 @@ impl Check for IsFile {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        if let Ok(m) = std::fs::metadata(&self.path) {
-            m.is_file()
-        } else {
-            false
-        }
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let yes = std::fs::metadata(path).is_ok_and(|m| m.is_file());
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -174,12 +321,10 @@ impl Check for IsDir {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        if let Ok(m) = std::fs::metadata(&self.path) {
-            m.is_dir()
-        } else {
-            false
-        }
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let yes = std::fs::metadata(path).is_ok_and(|m| m.is_dir());
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -213,8 +358,9 @@ impl Check for CanRead {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        std::fs::File::open(&self.path).is_ok()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        (std::fs::File::open(path).is_ok(), None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -248,13 +394,15 @@ impl Check for CanWrite {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        OpenOptions::new()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let yes = OpenOptions::new()
             .create(false)
             .append(true)
             .truncate(false)
-            .open(&self.path)
-            .is_ok()
+            .open(path)
+            .is_ok();
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -286,12 +434,12 @@ impl Check for PathIsMissing {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        if let Ok(y) = self.0.try_exists() {
-            !y
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        if let Ok(y) = ctx.resolve(&self.0).try_exists() {
+            (!y, None)
         } else {
             // check failed, not possible to answer if path is missing
-            false
+            (false, None)
         }
     }
 
@@ -326,12 +474,9 @@ impl Check for OrOp {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        if self.checks.is_empty() {
-            true
-        } else {
-            self.checks.iter().any(|c| c.yes())
-        }
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let yes = self.checks.is_empty() || self.checks.iter().any(|c| c.yes_in(ctx).0);
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -365,8 +510,15 @@ impl Check for AndOp {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        self.checks.iter().all(|c| c.yes())
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        for check in &self.checks {
+            match check.yes_in(ctx) {
+                (true, _) => {}
+                (false, Some(reason)) => return no(format!("`{}` is no: {reason}", check.name())),
+                (false, None) => return no(format!("`{}` is no", check.name())),
+            }
+        }
+        (true, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -385,15 +537,15 @@ where
 /// Checks if stdout output of the command contains provided pattern exactly
 /// once
 pub struct StdoutContainsOnce {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
     pattern: Pattern,
 }
 
 impl StdoutContainsOnce {
     const NAME: &'static str = "StdoutContainsOnce";
 
-    pub fn new(cmd: Vec<String>, pattern: Pattern) -> Self {
-        Self { cmd, pattern }
+    pub fn new(spec: ProcessSpec, pattern: Pattern) -> Self {
+        Self { spec, pattern }
     }
 }
 
@@ -402,12 +554,15 @@ impl Check for StdoutContainsOnce {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        let result = run(&self.cmd);
-        if let Some(ProcessOutput { stdout, .. }) = result.output {
-            self.pattern.contains_once(&stdout).unwrap_or_default()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        let Some(ProcessOutput { stdout, .. }) = result.output else {
+            return no(result.explain().unwrap_or_default());
+        };
+        if self.pattern.contains_once(&stdout).unwrap_or_default() {
+            (true, None)
         } else {
-            false
+            no(self.pattern.explain_not_once(&stdout))
         }
     }
 
@@ -417,26 +572,25 @@ impl Check for StdoutContainsOnce {
 }
 
 /// init [StdoutContainsOnce]
-pub fn stdout_contains_once<TCmd, TArg, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
+pub fn stdout_contains_once<TCmd, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
     TPattern: Into<Pattern>,
 {
-    StdoutContainsOnce::new(norm_cmd(cmd), pattern.into()).into_check()
+    StdoutContainsOnce::new(cmd.into(), pattern.into()).into_check()
 }
 
 /// Checks if stderr output of the command contains provided pattern exactly once
 pub struct StderrContainsOnce {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
     pattern: Pattern,
 }
 
 impl StderrContainsOnce {
     const NAME: &'static str = "StderrContainsOnce";
 
-    pub fn new(cmd: Vec<String>, pattern: Pattern) -> Self {
-        Self { cmd, pattern }
+    pub fn new(spec: ProcessSpec, pattern: Pattern) -> Self {
+        Self { spec, pattern }
     }
 }
 
@@ -445,12 +599,15 @@ impl Check for StderrContainsOnce {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        let result = run(&self.cmd);
-        if let Some(ProcessOutput { stderr, .. }) = result.output {
-            self.pattern.contains_once(&stderr).unwrap_or_default()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        let Some(ProcessOutput { stderr, .. }) = result.output else {
+            return no(result.explain().unwrap_or_default());
+        };
+        if self.pattern.contains_once(&stderr).unwrap_or_default() {
+            (true, None)
         } else {
-            false
+            no(self.pattern.explain_not_once(&stderr))
         }
     }
 
@@ -460,26 +617,25 @@ impl Check for StderrContainsOnce {
 }
 
 /// init [StderrContainsOnce]
-pub fn stderr_contains_once<TCmd, TArg, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
+pub fn stderr_contains_once<TCmd, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
     TPattern: Into<Pattern>,
 {
-    StderrContainsOnce::new(norm_cmd(cmd), pattern.into()).into_check()
+    StderrContainsOnce::new(cmd.into(), pattern.into()).into_check()
 }
 
 /// Checks if stdout of the command contains no provided pattern
 pub struct StdoutLacks {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
     pattern: Pattern,
 }
 
 impl StdoutLacks {
     const NAME: &'static str = "StdoutLacks";
 
-    pub fn new(cmd: Vec<String>, pattern: Pattern) -> Self {
-        Self { cmd, pattern }
+    pub fn new(spec: ProcessSpec, pattern: Pattern) -> Self {
+        Self { spec, pattern }
     }
 }
 
@@ -488,15 +644,15 @@ impl Check for StdoutLacks {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        let result = run(&self.cmd);
-        if let Some(ProcessOutput { stdout, .. }) = result.output {
-            self.pattern
-                .contains(&stdout)
-                .map(|y| !y)
-                .unwrap_or_default()
-        } else {
-            false
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        let Some(ProcessOutput { stdout, .. }) = result.output else {
+            return no(result.explain().unwrap_or_default());
+        };
+        match self.pattern.count(&stdout) {
+            Some(0) => (true, None),
+            Some(n) => no(format!("pattern matched {n} times")),
+            None => no("output is not utf8 text"),
         }
     }
 
@@ -506,26 +662,25 @@ impl Check for StdoutLacks {
 }
 
 /// init [StdoutLacks]
-pub fn stdout_lacks<TCmd, TArg, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
+pub fn stdout_lacks<TCmd, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
     TPattern: Into<Pattern>,
 {
-    StdoutLacks::new(norm_cmd(cmd), pattern.into()).into_check()
+    StdoutLacks::new(cmd.into(), pattern.into()).into_check()
 }
 
 /// Checks if stderr of the command contains no provided pattern
 pub struct StderrLacks {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
     pattern: Pattern,
 }
 
 impl StderrLacks {
     const NAME: &'static str = "StderrLacks";
 
-    pub fn new(cmd: Vec<String>, pattern: Pattern) -> Self {
-        Self { cmd, pattern }
+    pub fn new(spec: ProcessSpec, pattern: Pattern) -> Self {
+        Self { spec, pattern }
     }
 }
 
@@ -534,15 +689,15 @@ impl Check for StderrLacks {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        let result = run(&self.cmd);
-        if let Some(ProcessOutput { stderr, .. }) = result.output {
-            self.pattern
-                .contains(&stderr)
-                .map(|y| !y)
-                .unwrap_or_default()
-        } else {
-            false
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        let Some(ProcessOutput { stderr, .. }) = result.output else {
+            return no(result.explain().unwrap_or_default());
+        };
+        match self.pattern.count(&stderr) {
+            Some(0) => (true, None),
+            Some(n) => no(format!("pattern matched {n} times")),
+            None => no("output is not utf8 text"),
         }
     }
 
@@ -552,26 +707,25 @@ impl Check for StderrLacks {
 }
 
 /// init [StderrLacks]
-pub fn stderr_lacks<TCmd, TArg, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
+pub fn stderr_lacks<TCmd, TPattern>(cmd: TCmd, pattern: TPattern) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
     TPattern: Into<Pattern>,
 {
-    StderrLacks::new(norm_cmd(cmd), pattern.into()).into_check()
+    StderrLacks::new(cmd.into(), pattern.into()).into_check()
 }
 
 /// Checks if command returns provided [ExitCode] after execution
 pub struct IsExitCode {
-    cmd: Vec<String>,
+    spec: ProcessSpec,
     exit_code: ExitCode,
 }
 
 impl IsExitCode {
     const NAME: &'static str = "IsExitCode";
 
-    pub fn new(cmd: Vec<String>, exit_code: ExitCode) -> Self {
-        Self { cmd, exit_code }
+    pub fn new(spec: ProcessSpec, exit_code: ExitCode) -> Self {
+        Self { spec, exit_code }
     }
 }
 
@@ -580,8 +734,15 @@ impl Check for IsExitCode {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        run(&self.cmd).code == self.exit_code
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        if result.code == self.exit_code {
+            (true, None)
+        } else {
+            no(result
+                .explain()
+                .unwrap_or_else(|| "process exited successfully".to_owned()))
+        }
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -590,30 +751,69 @@ impl Check for IsExitCode {
 }
 
 /// init [IsExitCode], checks if exit code is [ExitCode::SuccessOnExit]
-pub fn command_ok<TCmd, TArg>(cmd: TCmd) -> Box<dyn Check>
+pub fn command_ok<TCmd>(cmd: TCmd) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
 {
-    IsExitCode::new(norm_cmd(cmd), ExitCode::SuccessOnExit).into_check()
+    IsExitCode::new(cmd.into(), ExitCode::SuccessOnExit).into_check()
 }
 
 /// init [IsExitCode], checks if exit code is [ExitCode::ErrorOnExit]
-pub fn command_err<TCmd, TArg>(cmd: TCmd) -> Box<dyn Check>
+pub fn command_err<TCmd>(cmd: TCmd) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
 {
-    IsExitCode::new(norm_cmd(cmd), ExitCode::ErrorOnExit).into_check()
+    IsExitCode::new(cmd.into(), ExitCode::ErrorOnExit).into_check()
 }
 
 /// init [IsExitCode], checks if exit code is [ExitCode::FailOnStart]
-pub fn command_fail<TCmd, TArg>(cmd: TCmd) -> Box<dyn Check>
+pub fn command_fail<TCmd>(cmd: TCmd) -> Box<dyn Check>
 where
-    TArg: Into<String>,
-    TCmd: Into<Vec<TArg>>,
+    TCmd: Into<ProcessSpec>,
 {
-    IsExitCode::new(norm_cmd(cmd), ExitCode::FailOnStart).into_check()
+    IsExitCode::new(cmd.into(), ExitCode::FailOnStart).into_check()
+}
+
+/// Checks if command exits with one of provided numeric exit statuses
+pub struct IsExitStatus {
+    spec: ProcessSpec,
+    statuses: Vec<i32>,
+}
+
+impl IsExitStatus {
+    const NAME: &'static str = "IsExitStatus";
+
+    pub fn new(spec: ProcessSpec, statuses: Vec<i32>) -> Self {
+        Self { spec, statuses }
+    }
+}
+
+impl Check for IsExitStatus {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let result = run_spec(&self.spec.in_context(ctx));
+        match result.status {
+            Some(status) if self.statuses.contains(&status) => (true, None),
+            Some(status) => no(format!("process exited with code {status}")),
+            None => no(result.explain().unwrap_or_default()),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [IsExitStatus]
+pub fn command_exit_code<TCmd, TStatuses>(cmd: TCmd, statuses: TStatuses) -> Box<dyn Check>
+where
+    TCmd: Into<ProcessSpec>,
+    TStatuses: Into<Vec<i32>>,
+{
+    IsExitStatus::new(cmd.into(), statuses.into()).into_check()
 }
 
 /// Checks if file matches exactly with provided content
@@ -635,12 +835,13 @@ impl Check for IsFileContent {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
         // todo: check file size equal content size first
-        if let Ok(file_content) = std::fs::read(&self.path) {
-            file_content == self.content
-        } else {
-            false
+        match std::fs::read(&path) {
+            Ok(file_content) if file_content == self.content => (true, None),
+            Ok(_) => no("file content differs"),
+            Err(e) => no(format!("can't read file: {e}")),
         }
     }
 
@@ -658,6 +859,56 @@ where
     IsFileContent::new(path.into(), content.into()).into_check()
 }
 
+/// Checks if file content equals to rendered template (see
+/// [template](crate::template)), confirms [WriteTemplate](crate::actions::WriteTemplate)
+pub struct IsRenderedTemplate {
+    path: PathBuf,
+    template: Template,
+    vars: Vars,
+}
+
+impl IsRenderedTemplate {
+    const NAME: &'static str = "IsRenderedTemplate";
+
+    pub fn new(path: PathBuf, template: Template, vars: Vars) -> Self {
+        Self {
+            path,
+            template,
+            vars,
+        }
+    }
+}
+
+impl Check for IsRenderedTemplate {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        match self.template.render(&self.vars) {
+            Ok(content) => IsFileContent::new(self.path.clone(), content.into()).yes_in(ctx),
+            Err(e) => no(format!("can't render template: {e}")),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [IsRenderedTemplate]
+pub fn is_rendered_template<FilePath, Tpl>(
+    path: FilePath,
+    template: Tpl,
+    vars: Vars,
+) -> Box<dyn Check>
+where
+    FilePath: Into<PathBuf>,
+    Tpl: Into<Template>,
+{
+    IsRenderedTemplate::new(path.into(), template.into(), vars).into_check()
+}
+
 /// Checks if file contains provided pattern exactly once
 pub struct FileContainsOnce {
     path: PathBuf,
@@ -677,13 +928,14 @@ impl Check for FileContainsOnce {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        if let Ok(file_content) = std::fs::read(&self.path) {
-            self.pattern
-                .contains_once(&file_content)
-                .unwrap_or_default()
-        } else {
-            false
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        match std::fs::read(&path) {
+            Ok(file_content) if self.pattern.contains_once(&file_content) == Some(true) => {
+                (true, None)
+            }
+            Ok(file_content) => no(self.pattern.explain_not_once(&file_content)),
+            Err(e) => no(format!("can't read file: {e}")),
         }
     }
 
@@ -701,6 +953,207 @@ where
     FileContainsOnce::new(path.into(), data.into()).into_check()
 }
 
+/// Checks if file has line and no other line matches pattern, confirms
+/// [EnsureLine](crate::actions::EnsureLine)
+pub struct FileHasLine {
+    path: PathBuf,
+    line: Vec<u8>,
+    pattern: Pattern,
+}
+
+impl FileHasLine {
+    const NAME: &'static str = "FileHasLine";
+
+    pub fn new(path: PathBuf, line: Vec<u8>, pattern: Pattern) -> Self {
+        Self {
+            path,
+            line,
+            pattern,
+        }
+    }
+}
+
+impl Check for FileHasLine {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) => return no(format!("can't read file: {e}")),
+        };
+        if lines::ensure_line(&content, &self.line, &self.pattern, &LinePosition::End) == content {
+            return (true, None);
+        }
+        match lines::count_lines(&content, &self.pattern) {
+            0 => no("line not found"),
+            n => no(format!("pattern matched {n} lines")),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [FileHasLine], `line` is text of line without newline
+pub fn file_has_line<TPath, TLine, TPattern>(
+    path: TPath,
+    line: TLine,
+    pattern: TPattern,
+) -> Box<dyn Check>
+where
+    TPath: Into<PathBuf>,
+    TLine: Into<Vec<u8>>,
+    TPattern: Into<Pattern>,
+{
+    FileHasLine::new(path.into(), line.into(), pattern.into()).into_check()
+}
+
+/// Checks if no line of file matches pattern (missing file has no lines),
+/// confirms [EnsureLineAbsent](crate::actions::EnsureLineAbsent)
+pub struct FileLacksLine {
+    path: PathBuf,
+    pattern: Pattern,
+}
+
+impl FileLacksLine {
+    const NAME: &'static str = "FileLacksLine";
+
+    pub fn new(path: PathBuf, pattern: Pattern) -> Self {
+        Self { path, pattern }
+    }
+}
+
+impl Check for FileLacksLine {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (true, None),
+            Err(e) => return no(format!("can't read file: {e}")),
+        };
+        match lines::count_lines(&content, &self.pattern) {
+            0 => (true, None),
+            n => no(format!("pattern matched {n} lines")),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [FileLacksLine]
+pub fn file_lacks_line<TPath, TPattern>(path: TPath, pattern: TPattern) -> Box<dyn Check>
+where
+    TPath: Into<PathBuf>,
+    TPattern: Into<Pattern>,
+{
+    FileLacksLine::new(path.into(), pattern.into()).into_check()
+}
+
+/// Checks if file has block with provided id and content, confirms
+/// [EnsureBlock](crate::actions::EnsureBlock)
+pub struct FileHasBlock {
+    path: PathBuf,
+    id: String,
+    block: Vec<u8>,
+}
+
+impl FileHasBlock {
+    const NAME: &'static str = "FileHasBlock";
+
+    pub fn new(path: PathBuf, id: String, block: Vec<u8>) -> Self {
+        Self { path, id, block }
+    }
+}
+
+impl Check for FileHasBlock {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        match std::fs::read(&path) {
+            Ok(content) => match lines::explain_no_block(&content, &self.id, &self.block) {
+                None => (true, None),
+                Some(reason) => no(reason),
+            },
+            Err(e) => no(format!("can't read file: {e}")),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [FileHasBlock]
+pub fn file_has_block<TPath, TId, TBlock>(path: TPath, id: TId, block: TBlock) -> Box<dyn Check>
+where
+    TPath: Into<PathBuf>,
+    TId: Into<String>,
+    TBlock: Into<Vec<u8>>,
+{
+    FileHasBlock::new(path.into(), id.into(), block.into()).into_check()
+}
+
+/// Checks if file has no block with provided id (missing file has no blocks),
+/// confirms [RemoveBlock](crate::actions::RemoveBlock)
+pub struct FileLacksBlock {
+    path: PathBuf,
+    id: String,
+}
+
+impl FileLacksBlock {
+    const NAME: &'static str = "FileLacksBlock";
+
+    pub fn new(path: PathBuf, id: String) -> Self {
+        Self { path, id }
+    }
+}
+
+impl Check for FileLacksBlock {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        let path = ctx.resolve(&self.path);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (true, None),
+            Err(e) => return no(format!("can't read file: {e}")),
+        };
+        match lines::has_block(&content, &self.id) {
+            Ok(false) => (true, None),
+            Ok(true) => no("block found"),
+            Err(reason) => no(reason),
+        }
+    }
+
+    fn into_check(self) -> Box<dyn Check> {
+        Box::new(self)
+    }
+}
+
+/// init [FileLacksBlock]
+pub fn file_lacks_block<TPath, TId>(path: TPath, id: TId) -> Box<dyn Check>
+where
+    TPath: Into<PathBuf>,
+    TId: Into<String>,
+{
+    FileLacksBlock::new(path.into(), id.into()).into_check()
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ServiceStatus {
     Active,
@@ -727,13 +1180,13 @@ impl Check for ServiceInStatus {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
         let result = run(&[
             "/usr/bin/systemctl".to_owned(),
             "is-active".to_owned(),
             self.service.clone(),
         ]);
-        if let Some(ProcessOutput { stdout, .. }) = result.output {
+        let yes = if let Some(ProcessOutput { stdout, .. }) = result.output {
             let status = String::from_utf8(stdout).unwrap_or("".to_owned());
             let status = status.trim();
             status
@@ -744,7 +1197,8 @@ impl Check for ServiceInStatus {
                 }
         } else {
             false
-        }
+        };
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -798,13 +1252,13 @@ impl Check for ServiceIsEnabled {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
+    fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
         let result = run(&[
             "/usr/bin/systemctl".to_owned(),
             "is-enabled".to_owned(),
             self.service.clone(),
         ]);
-        if let Some(ProcessOutput { stdout, .. }) = result.output {
+        let yes = if let Some(ProcessOutput { stdout, .. }) = result.output {
             let status = String::from_utf8(stdout).unwrap_or("".to_owned());
             let status = status.trim();
             status
@@ -815,7 +1269,8 @@ impl Check for ServiceIsEnabled {
                 }
         } else {
             false
-        }
+        };
+        (yes, None)
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -839,18 +1294,23 @@ where
     ServiceIsEnabled::new(service.into(), false).into_check()
 }
 
-/// Implements [Check] for tuple with name and function
+/// Implements [Check] for tuple with name and function, function does not
+/// receive [ExecContext], so current directory of process is changed to
+/// directory of context while it runs (see [in_dir])
 impl<N, F> Check for (N, F)
 where
-    N: AsRef<str> + 'static,
-    F: Fn() -> bool + 'static,
+    N: AsRef<str> + Send + Sync + 'static,
+    F: Fn() -> bool + Send + Sync + 'static,
 {
     fn name(&self) -> &str {
         self.0.as_ref()
     }
 
-    fn yes(&self) -> bool {
-        self.1()
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        match in_dir(ctx, || self.1()) {
+            Ok(yes) => (yes, None),
+            Err(reason) => no(reason),
+        }
     }
 
     fn into_check(self) -> Box<dyn Check> {
@@ -861,7 +1321,7 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::pattern::re;
+    use crate::{pattern::re, template::vars};
     use std::path::Path;
 
     const NOT_A_FILE: &str = "/tmp/not-a-pass-test-file-5555555555";
@@ -898,6 +1358,30 @@ mod test {
         assert!(!c.yes());
     }
 
+    #[test]
+    fn test_timeout() {
+        assert!(timeout(Duration::from_secs(5), command_ok(["true"])).yes());
+        let time_a = Instant::now();
+        assert!(!timeout(Duration::from_millis(200), command_ok(["sleep", "10"])).yes());
+        assert!(time_a.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_facts() {
+        crate::facts::scope(|| {
+            assert!(!has_fact("ip").yes());
+            assert!(!fact_is("ip", "127.0.0.1").yes());
+            crate::facts::set_fact("ip", "127.0.0.1");
+            assert!(has_fact("ip").yes());
+            assert!(fact_is("ip", "127.0.0.1").yes());
+            assert!(!fact_is("ip", "10.0.0.1").yes());
+            let ip_is_local = lazy("ip is local", |facts| {
+                stdout_contains_once(["echo", facts.get("ip").unwrap_or_default()], "127.")
+            });
+            assert!(ip_is_local.yes());
+        });
+    }
+
     #[test]
     fn test_user_is_root() {
         // use `test_user_is_root` example for manual testing
@@ -966,6 +1450,17 @@ mod test {
         assert!(!stdout_contains_once(["echo", "1112222"], "44").yes());
         assert!(stdout_contains_once(["echo", "111222333"], re("1.2")).yes());
         assert!(!stdout_contains_once(["echo", "111222333"], re("1.")).yes());
+        assert_eq!(
+            stdout_contains_once(["echo", "1112222"], "22").yes_explained(),
+            (false, Some("pattern matched 2 times".to_owned()))
+        );
+        assert_eq!(
+            and_op([always_yes(), stdout_contains_once(["echo", "1"], "2")]).yes_explained(),
+            (
+                false,
+                Some("`StdoutContainsOnce` is no: pattern not found".to_owned())
+            )
+        );
     }
 
     #[test]
@@ -1008,6 +1503,21 @@ mod test {
         assert!(command_fail(["/bin/command-which-does-not-exist-5kGHx7FDlwolHKpmJQim9X"]).yes());
     }
 
+    #[test]
+    fn test_is_exit_status() {
+        assert!(command_exit_code(["sh", "-c", "exit 3"], [0, 3]).yes());
+        assert!(command_exit_code(["/bin/true"], [0, 3]).yes());
+        assert_eq!(
+            command_exit_code(["/bin/false"], [0, 3]).yes_explained(),
+            (false, Some("process exited with code 1".to_owned()))
+        );
+        assert!(!command_exit_code(
+            ["/bin/command-which-does-not-exist-5kGHx7FDlwolHKpmJQim9X"],
+            [0]
+        )
+        .yes());
+    }
+
     #[test]
     fn test_is_file_content() {
         let path = create_test_file("is_file_content");
@@ -1016,6 +1526,73 @@ mod test {
         delete_test_file(&path);
     }
 
+    #[test]
+    fn test_is_rendered_template() {
+        let path = create_test_file("is_rendered_template");
+        let vars = vars().var("b", "bbb");
+        assert!(is_rendered_template(&path, "aaa{{ b }}ccc", vars.clone()).yes());
+        assert!(!is_rendered_template(&path, "aaa{{ c }}ccc", vars.clone()).yes());
+        assert_eq!(
+            is_rendered_template(&path, "{% if b %}", vars).yes_explained(),
+            (
+                false,
+                Some("can't render template: line 1: `if` is not closed".to_owned())
+            )
+        );
+        delete_test_file(path);
+    }
+
+    #[test]
+    fn test_file_has_line() {
+        let path = create_test_file("file_has_line");
+        std::fs::write(&path, "a 1\nb 2\nb 3\n").unwrap();
+        assert!(file_has_line(&path, "a 1", re("^a ")).yes());
+        assert_eq!(
+            file_has_line(&path, "b 2", re("^b ")).yes_explained(),
+            (false, Some("pattern matched 2 lines".to_owned()))
+        );
+        assert_eq!(
+            file_has_line(&path, "c 1", re("^c ")).yes_explained(),
+            (false, Some("line not found".to_owned()))
+        );
+        assert!(file_lacks_line(&path, re("^c ")).yes());
+        assert!(!file_lacks_line(&path, re("^b ")).yes());
+        delete_test_file(&path);
+        assert!(file_lacks_line(&path, re("^b ")).yes());
+        assert!(!file_has_line(&path, "a 1", re("^a ")).yes());
+    }
+
+    #[test]
+    fn test_file_has_block() {
+        let path = create_test_file("file_has_block");
+        std::fs::write(&path, "x\n# BEGIN pass db\n10.0.0.2 db\n# END pass db\n").unwrap();
+        assert!(file_has_block(&path, "db", "10.0.0.2 db\n").yes());
+        assert_eq!(
+            file_has_block(&path, "db", "10.0.0.3 db").yes_explained(),
+            (false, Some("block differs".to_owned()))
+        );
+        assert_eq!(
+            file_has_block(&path, "other", "").yes_explained(),
+            (false, Some("block not found".to_owned()))
+        );
+        assert!(file_lacks_block(&path, "other").yes());
+        assert_eq!(
+            file_lacks_block(&path, "db").yes_explained(),
+            (false, Some("block found".to_owned()))
+        );
+        std::fs::write(&path, "# END pass db\n").unwrap();
+        assert_eq!(
+            file_lacks_block(&path, "db").yes_explained(),
+            (
+                false,
+                Some("begin marker of block `db` not found".to_owned())
+            )
+        );
+        delete_test_file(&path);
+        assert!(!file_has_block(&path, "db", "10.0.0.2 db").yes());
+        assert!(file_lacks_block(&path, "db").yes());
+    }
+
     #[test]
     fn test_file_contains_once() {
         let path = create_test_file("file_contains_once");
@@ -1,11 +1,79 @@
 use clap::{Parser, Subcommand};
 
-use crate::{interfaces::ActionResult, Playbook};
+use crate::{
+    playbook::{ExternalOutput, Start, TagFilter},
+    OutputFormat, Playbook,
+};
+
+// Selects instructions by tags (not a doc comment, clap would use it as
+// description of application)
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Apply only instructions with any of provided tags (comma separated)
+    #[arg(long, global = true, value_delimiter = ',')]
+    tags: Vec<String>,
+    /// Skip instructions with any of provided tags (comma separated)
+    #[arg(long, global = true, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+}
+
+impl FilterArgs {
+    fn filter(self) -> TagFilter {
+        TagFilter {
+            tags: self.tags,
+            skip_tags: self.skip_tags,
+        }
+    }
+}
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Format of playbook output
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// What is done with output of external processes run by actions (by
+    /// default as set in playbook)
+    #[arg(long, value_enum, global = true)]
+    external_output: Option<ExternalOutput>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// Selects instructions to apply
+#[derive(clap::Args)]
+struct StartArgs {
+    /// Start from instruction which failed during previous run
+    #[arg(long, conflicts_with_all = ["from", "only"])]
+    resume: bool,
+    /// Start from instruction with provided number (as shown in output) or
+    /// action name
+    #[arg(long, conflicts_with = "only")]
+    from: Option<String>,
+    /// Apply only instruction with provided number (as shown in output) or
+    /// action name
+    #[arg(long)]
+    only: Option<String>,
+}
+
+impl StartArgs {
+    fn start(&self, playbook: &Playbook) -> Result<Start, String> {
+        let find = |number_or_name: &str| {
+            playbook
+                .find_instruction(number_or_name)
+                .ok_or_else(|| format!("instruction `{number_or_name}` not found"))
+        };
+        if self.resume {
+            Ok(Start::Resume)
+        } else if let Some(from) = &self.from {
+            Ok(Start::From(find(from)?))
+        } else if let Some(only) = &self.only {
+            Ok(Start::Only(find(only)?))
+        } else {
+            Ok(Start::First)
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -14,12 +82,45 @@ enum Commands {
     About,
     /// Show source code of playbook
     Source,
+    /// Apply playbook (same as running without command)
+    Apply {
+        #[command(flatten)]
+        start: StartArgs,
+    },
+    /// Check what would change if playbook applied, without applying it
+    /// (exits with error if anything would change)
+    Check {
+        #[command(flatten)]
+        start: StartArgs,
+    },
+    /// Show issues found in playbook without applying or checking it (exits
+    /// with error if any error is found)
+    Lint,
+    /// List runs of playbook with backups, or files backed up during run
+    Backups {
+        /// Name of run (as listed)
+        run: Option<String>,
+    },
+    /// Restore files backed up during run
+    Restore {
+        /// Name of run (as listed by `backups` command)
+        run: String,
+    },
 }
 
 #[derive(Parser)]
 struct ArgsWithInput {
     #[command(subcommand)]
     command: CommandsWithInput,
+    /// Format of playbook output
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// What is done with output of external processes run by actions (by
+    /// default as set in playbook)
+    #[arg(long, value_enum, global = true)]
+    external_output: Option<ExternalOutput>,
+    #[command(flatten)]
+    filter: FilterArgs,
 }
 
 #[derive(Subcommand)]
@@ -35,7 +136,98 @@ enum CommandsWithInput {
     Apply {
         /// Input data for playbook
         input: String,
+        #[command(flatten)]
+        start: StartArgs,
     },
+    /// Check what would change if playbook applied, without applying it
+    /// (exits with error if anything would change)
+    Check {
+        /// Input data for playbook
+        input: String,
+        #[command(flatten)]
+        start: StartArgs,
+    },
+    /// Show issues found in playbook without applying or checking it (exits
+    /// with error if any error is found)
+    Lint {
+        /// Input data for playbook
+        input: String,
+    },
+    /// List runs of playbook with backups, or files backed up during run
+    Backups {
+        /// Input data for playbook
+        input: String,
+        /// Name of run (as listed)
+        run: Option<String>,
+    },
+    /// Restore files backed up during run
+    Restore {
+        /// Input data for playbook
+        input: String,
+        /// Name of run (as listed by `backups` command)
+        run: String,
+    },
+}
+
+fn with_start(playbook: Playbook, start: &StartArgs) -> Playbook {
+    match start.start(&playbook) {
+        Ok(start) => playbook.with_start(start),
+        Err(e) => {
+            println!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn apply(playbook: Playbook, start: &StartArgs) {
+    if !with_start(playbook, start).apply().ok() {
+        std::process::exit(1);
+    }
+}
+
+fn check(playbook: Playbook, start: &StartArgs) {
+    if with_start(playbook, start).check().drift() {
+        std::process::exit(1);
+    }
+}
+
+fn lint(playbook: &Playbook) {
+    let issues = playbook.validate();
+    for issue in &issues {
+        println!("{issue}");
+    }
+    if issues.iter().any(|i| i.is_error()) {
+        std::process::exit(1);
+    }
+}
+
+fn backups(playbook: &Playbook, run: Option<&str>) {
+    match run {
+        Some(run) => {
+            for file in playbook.backup_files(run) {
+                println!("{}", file.display());
+            }
+        }
+        None => {
+            for run in playbook.backup_runs() {
+                println!("{run}");
+            }
+        }
+    }
+}
+
+fn restore(playbook: &Playbook, run: &str) {
+    match playbook.restore_backup(run) {
+        Ok(files) => {
+            for file in files {
+                println!("restored {}", file.display());
+            }
+        }
+        Err(e) => {
+            println!("{e}");
+            std::process::exit(1);
+        }
+    }
 }
 
 fn print_about(playbook: &Playbook) {
@@ -44,14 +236,39 @@ fn print_about(playbook: &Playbook) {
     println!("{}", playbook.description);
 }
 
+/// Applies options shared by all commands
+fn configure(
+    playbook: Playbook,
+    output: OutputFormat,
+    external_output: Option<ExternalOutput>,
+    filter: TagFilter,
+) -> Playbook {
+    let playbook = playbook.with_output(output).with_filter(filter);
+    match external_output {
+        Some(external_output) => playbook.with_external_output(external_output),
+        None => playbook,
+    }
+}
+
 pub fn run_cli(playbook: Playbook, source: &'static str) {
     let args = Args::parse();
+    let playbook = configure(
+        playbook,
+        args.output,
+        args.external_output,
+        args.filter.filter(),
+    );
     if let Some(cmd) = args.command {
         match cmd {
             Commands::About => print_about(&playbook),
             Commands::Source => println!("{source}"),
+            Commands::Apply { start } => apply(playbook, &start),
+            Commands::Check { start } => check(playbook, &start),
+            Commands::Lint => lint(&playbook),
+            Commands::Backups { run } => backups(&playbook, run.as_deref()),
+            Commands::Restore { run } => restore(&playbook, &run),
         }
-    } else if playbook.apply() == ActionResult::Fail {
+    } else if !playbook.apply().ok() {
         std::process::exit(1);
     }
 }
@@ -70,6 +287,7 @@ pub fn run_cli_with_input<GetPlaybook>(
     GetPlaybook: FnOnce(&[u8]) -> Result<Playbook, String>,
 {
     let args = ArgsWithInput::parse();
+    let filter = args.filter.filter();
     match args.command {
         CommandsWithInput::About { input: Some(input) } => match get_playbook(input.as_bytes()) {
             Ok(pb) => {
@@ -86,12 +304,42 @@ pub fn run_cli_with_input<GetPlaybook>(
             println!("{input_help}");
         }
         CommandsWithInput::Source => println!("{source}"),
-        CommandsWithInput::Apply { input } => match get_playbook(input.as_bytes()) {
-            Ok(pb) => {
-                if pb.apply() == ActionResult::Fail {
-                    std::process::exit(1);
-                }
+        CommandsWithInput::Apply { input, start } => match get_playbook(input.as_bytes()) {
+            Ok(pb) => apply(
+                configure(pb, args.output, args.external_output, filter),
+                &start,
+            ),
+            Err(e) => {
+                println!("{e}");
+                std::process::exit(1);
+            }
+        },
+        CommandsWithInput::Check { input, start } => match get_playbook(input.as_bytes()) {
+            Ok(pb) => check(
+                configure(pb, args.output, args.external_output, filter),
+                &start,
+            ),
+            Err(e) => {
+                println!("{e}");
+                std::process::exit(1);
+            }
+        },
+        CommandsWithInput::Lint { input } => match get_playbook(input.as_bytes()) {
+            Ok(pb) => lint(&pb),
+            Err(e) => {
+                println!("{e}");
+                std::process::exit(1);
+            }
+        },
+        CommandsWithInput::Backups { input, run } => match get_playbook(input.as_bytes()) {
+            Ok(pb) => backups(&pb, run.as_deref()),
+            Err(e) => {
+                println!("{e}");
+                std::process::exit(1);
             }
+        },
+        CommandsWithInput::Restore { input, run } => match get_playbook(input.as_bytes()) {
+            Ok(pb) => restore(&pb, &run),
             Err(e) => {
                 println!("{e}");
                 std::process::exit(1);
@@ -11,6 +11,10 @@ use crate::{
     Playbook,
 };
 
+/// Directory where [Playbook] saves number of failed instruction, used to
+/// resume failed run (see [Start::Resume](crate::playbook::Start::Resume))
+pub const PROGRESS_DIR: &str = "/srv/pass/progress";
+
 fn dep_flag_path<Name>(name: Name) -> PathBuf
 where
     Name: AsRef<str>,
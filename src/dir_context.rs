@@ -1,9 +1,84 @@
-use std::path::{Path, PathBuf};
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
 
-use crate::interfaces::{Action, ActionResult, Check};
+use crate::actions::fail;
+use crate::interfaces::{Action, ActionResult, Check, ExecContext};
 
-/// Changes current working directory for action or check (and revert back after
-/// it)
+/// Serializes changes of current directory of process made by [in_dir] and
+/// [SetDir](crate::actions::SetDir)
+static DIR_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// Set while current thread holds [DIR_LOCK], so nested [in_dir] does not
+    /// lock it again
+    static HOLDS_DIR_LOCK: Cell<bool> = const { Cell::new(false) };
+}
+
+struct DirLock {
+    guard: Option<MutexGuard<'static, ()>>,
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if self.guard.is_some() {
+            HOLDS_DIR_LOCK.set(false);
+        }
+    }
+}
+
+fn lock_dir() -> DirLock {
+    if HOLDS_DIR_LOCK.get() {
+        return DirLock { guard: None };
+    }
+    let guard = DIR_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    HOLDS_DIR_LOCK.set(true);
+    DirLock { guard: Some(guard) }
+}
+
+/// Runs `f` while no other thread changes current directory of process with
+/// [in_dir] or [SetDir](crate::actions::SetDir)
+pub(crate) fn with_dir_lock<T>(f: impl FnOnce() -> T) -> T {
+    let _lock = lock_dir();
+    f()
+}
+
+/// Runs `f` with current directory of process changed to directory of context
+/// (and reverted back after it), used for closures which don't receive
+/// [ExecContext]. Changes are serialized with a lock, but code running
+/// meanwhile in other threads sees changed directory
+pub fn in_dir<T>(ctx: &ExecContext, f: impl FnOnce() -> T) -> Result<T, String> {
+    let Some(dir) = ctx.dir() else {
+        return Ok(f());
+    };
+    with_dir_lock(|| {
+        let current =
+            std::env::current_dir().map_err(|e| format!("can't get current directory: {e}"))?;
+        std::env::set_current_dir(dir).map_err(|e| format!("can't change directory: {e}"))?;
+        let result = f();
+        std::env::set_current_dir(current)
+            .map_err(|e| format!("can't change directory back: {e}"))?;
+        Ok(result)
+    })
+}
+
+/// Nested context with provided working directory, fails if it is not an
+/// existing directory
+fn enter(ctx: &ExecContext, path: &Path) -> Result<ExecContext, String> {
+    let ctx = ctx.with_dir(path);
+    match ctx.dir().map(std::fs::metadata) {
+        Some(Ok(m)) if m.is_dir() => Ok(ctx),
+        Some(Ok(_)) => Err("can't change directory: not a directory".to_owned()),
+        Some(Err(e)) => Err(format!("can't change directory: {e}")),
+        None => Err("can't change directory".to_owned()),
+    }
+}
+
+/// Runs action or check with provided working directory, it is passed in
+/// [ExecContext], current directory of process stays unchanged (except for
+/// closures, see [in_dir])
 pub struct DirContext(PathBuf);
 
 impl DirContext {
@@ -34,7 +109,8 @@ where
     DirContext(path.into())
 }
 
-/// Changes current working directory for check (and revert back after it)
+/// Runs check with provided working directory, relative path is resolved
+/// against directory of outer context
 pub struct DirContextCheck {
     path: PathBuf,
     check: Box<dyn Check>,
@@ -53,18 +129,10 @@ impl Check for DirContextCheck {
         Self::NAME
     }
 
-    fn yes(&self) -> bool {
-        let Ok(current_dir) = std::env::current_dir() else {
-            return false;
-        };
-        if std::env::set_current_dir(&self.path).is_err() {
-            return false;
-        }
-        let result = self.check.yes();
-        if std::env::set_current_dir(current_dir).is_ok() {
-            result
-        } else {
-            false
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>) {
+        match enter(ctx, &self.path) {
+            Ok(ctx) => self.check.yes_in(&ctx),
+            Err(reason) => (false, Some(reason)),
         }
     }
 
@@ -73,7 +141,8 @@ impl Check for DirContextCheck {
     }
 }
 
-/// Changes current working directory for action (and revert back after it)
+/// Runs action with provided working directory, relative path is resolved
+/// against directory of outer context
 pub struct DirContextAction {
     path: PathBuf,
     action: Box<dyn Action>,
@@ -92,21 +161,21 @@ impl Action for DirContextAction {
         Self::NAME
     }
 
-    fn run(&self) -> ActionResult {
-        let Ok(current_dir) = std::env::current_dir() else {
-            return ActionResult::Fail;
-        };
-        if std::env::set_current_dir(&self.path).is_err() {
-            return ActionResult::Fail;
-        }
-        let result = self.action.run();
-        if std::env::set_current_dir(current_dir).is_ok() {
-            result
-        } else {
-            ActionResult::Fail
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>) {
+        match enter(ctx, &self.path) {
+            Ok(ctx) => self.action.run_in(&ctx),
+            Err(reason) => fail(reason),
         }
     }
 
+    fn undo_in(&self, ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        // undo is prepared inside of directory, because action can use
+        // relative paths
+        let ctx = enter(ctx, &self.path).ok()?;
+        let undo = self.action.undo_in(&ctx)?;
+        Some(DirContextAction::new(ctx.dir()?.to_path_buf(), undo).into_action())
+    }
+
     fn into_action(self) -> Box<dyn Action> {
         Box::new(self)
     }
@@ -116,47 +185,74 @@ impl Action for DirContextAction {
 mod test {
     use super::*;
     use crate::{
-        actions::{always_ok, set_dir},
-        checks::always_yes,
+        actions::{always_ok, command, many, set_dir, write_file},
+        checks::{always_yes, is_file, stdout_contains_once},
     };
 
     #[test]
     fn test_dir_context() {
-        // all tests related to current directory should be run sequentially,
-        // because current directory is global for process
         {
             // test check dir context
             let dir_path: PathBuf = "/tmp/pass-test-dir-111222333-test_dir_context_check".into();
             std::fs::create_dir(&dir_path).unwrap();
+            std::fs::write(dir_path.join("file"), "").unwrap();
             let current = std::env::current_dir().unwrap();
-            let dir_copy = dir_path.clone();
-            assert!(dir(&dir_path)
-                .check(
-                    ("test dir context check", move || {
-                        std::env::current_dir().unwrap() == dir_copy
-                    })
-                        .into_check()
-                )
-                .yes());
-            assert_ne!(std::env::current_dir().unwrap(), dir_path);
+            assert!(dir(&dir_path).check(is_file("file")).yes());
             assert_eq!(std::env::current_dir().unwrap(), current);
             assert!(!dir("/aaaaaaaaaaaaaa/bbbbbbbbbbbbb/11111111111/error-path")
                 .check(always_yes())
                 .yes());
-            std::fs::remove_dir(dir_path).unwrap();
-            assert_eq!(std::env::current_dir().unwrap(), current);
+            std::fs::remove_dir_all(dir_path).unwrap();
         }
         {
             // test action dir context
             let dir_path: PathBuf = "/tmp/pass-test-dir-111222333-test_dir_context_action".into();
             std::fs::create_dir(&dir_path).unwrap();
             let current = std::env::current_dir().unwrap();
+            assert_eq!(
+                dir(&dir_path).action(write_file("file", "")).run(),
+                ActionResult::Ok
+            );
+            assert!(dir_path.join("file").is_file());
+            assert_eq!(std::env::current_dir().unwrap(), current);
+            assert_eq!(
+                dir("/aaaaaaaaaaaaaa/bbbbbbbbbbbbb/11111111111/error-path")
+                    .action(always_ok())
+                    .run(),
+                ActionResult::Fail
+            );
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+        {
+            // closures don't receive context, current directory of process is
+            // changed while they run
+            let dir_path: PathBuf = "/tmp/pass-test-dir-111222333-test_dir_context_closure".into();
+            std::fs::create_dir_all(dir_path.join("inner")).unwrap();
+            let current = std::env::current_dir().unwrap();
+            let dir_copy = dir_path.clone();
+            assert!(dir(&dir_path)
+                .check(
+                    ("test dir context check", move || {
+                        std::env::current_dir().unwrap() == dir_copy
+                    })
+                        .into_check()
+                )
+                .yes());
+            assert_eq!(std::env::current_dir().unwrap(), current);
             let dir_copy = dir_path.clone();
             assert_eq!(
                 dir(&dir_path)
                     .action(
                         ("test dir context action", move || {
-                            if std::env::current_dir().unwrap() == dir_copy {
+                            // nested closure does not wait for lock held by
+                            // outer one
+                            let inner = dir("inner").check(
+                                ("test nested dir context", || {
+                                    std::env::current_dir().unwrap().ends_with("inner")
+                                })
+                                    .into_check(),
+                            );
+                            if std::env::current_dir().unwrap() == dir_copy && inner.yes() {
                                 ActionResult::Ok
                             } else {
                                 ActionResult::Fail
@@ -167,18 +263,11 @@ mod test {
                     .run(),
                 ActionResult::Ok
             );
-            assert_ne!(std::env::current_dir().unwrap(), dir_path);
-            assert_eq!(std::env::current_dir().unwrap(), current);
-            assert_eq!(
-                dir("/aaaaaaaaaaaaaa/bbbbbbbbbbbbb/11111111111/error-path")
-                    .action(always_ok())
-                    .run(),
-                ActionResult::Fail
-            );
-            std::fs::remove_dir(dir_path).unwrap();
             assert_eq!(std::env::current_dir().unwrap(), current);
+            std::fs::remove_dir_all(dir_path).unwrap();
         }
         {
+            // setting current directory of process is still possible
             let dir: PathBuf = "/tmp/pass-test-dir-111222333-set-dir".into();
             std::fs::create_dir(&dir).unwrap();
             let current = std::env::current_dir().unwrap();
@@ -192,4 +281,40 @@ mod test {
             std::fs::remove_dir(dir).unwrap();
         }
     }
+
+    #[test]
+    fn test_relative_paths() {
+        let root: PathBuf = "/tmp/pass-test-dir-111222333-test_relative_paths".into();
+        std::fs::create_dir_all(root.join("inner")).unwrap();
+        let ctx = ExecContext::default();
+        assert_eq!(ctx.resolve("a"), PathBuf::from("a"));
+        let ctx = ctx.with_dir(&root);
+        assert_eq!(ctx.resolve("a"), root.join("a"));
+        assert_eq!(ctx.resolve("/a"), PathBuf::from("/a"));
+        assert_eq!(
+            ctx.with_dir("inner").dir(),
+            Some(root.join("inner").as_path())
+        );
+        // context is passed through nested actions
+        let write = dir(&root).action(many([dir("inner").action(write_file("file", "data"))]));
+        assert_eq!(write.run(), ActionResult::Ok);
+        assert!(dir(&root).check(is_file("inner/file")).yes());
+        assert!(!is_file("inner/file").yes());
+        assert!(is_file("inner/file").yes_in(&ctx).0);
+        assert!(dir(root.join("inner"))
+            .check(stdout_contains_once(["ls"], "file"))
+            .yes());
+        assert_eq!(
+            dir(&root).action(command(["rm", "inner/file"])).run(),
+            ActionResult::Ok
+        );
+        // undo is prepared in the same directory
+        let write = dir(&root).action(write_file("new", ""));
+        let undo = write.undo().unwrap();
+        assert_eq!(write.run(), ActionResult::Ok);
+        assert_eq!(undo.run(), ActionResult::Ok);
+        assert!(!root.join("new").exists());
+        std::fs::remove_dir(root.join("inner")).unwrap();
+        std::fs::remove_dir(root).unwrap();
+    }
 }
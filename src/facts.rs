@@ -0,0 +1,93 @@
+//! Facts are named values shared between instructions while
+//! [Playbook](crate::Playbook) is applied, actions can store facts (see
+//! [command_output_into](crate::actions::command_output_into)) and later
+//! actions and checks can read them (see [lazy](crate::actions::lazy))
+
+use std::{cell::RefCell, collections::HashMap};
+
+/// Named values known during current run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Facts(HashMap<String, String>);
+
+impl Facts {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|v| v.as_str())
+    }
+
+    pub fn set<Name, Value>(&mut self, name: Name, value: Value)
+    where
+        Name: Into<String>,
+        Value: Into<String>,
+    {
+        self.0.insert(name.into(), value.into());
+    }
+}
+
+thread_local! {
+    static FACTS: RefCell<Facts> = RefCell::new(Facts::default());
+}
+
+/// Stores fact for current run
+pub fn set_fact<Name, Value>(name: Name, value: Value)
+where
+    Name: Into<String>,
+    Value: Into<String>,
+{
+    FACTS.with(|f| f.borrow_mut().set(name, value));
+}
+
+/// Returns fact stored during current run
+pub fn get_fact(name: &str) -> Option<String> {
+    FACTS.with(|f| f.borrow().get(name).map(|v| v.to_owned()))
+}
+
+/// Calls function with all facts of current run
+pub fn with_facts<F, R>(f: F) -> R
+where
+    F: FnOnce(&Facts) -> R,
+{
+    FACTS.with(|facts| f(&facts.borrow().clone()))
+}
+
+/// Runs function with empty facts, facts stored inside of it are dropped when
+/// it finishes, used for each run of playbook
+pub fn scope<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    scope_with(Facts::default(), f).0
+}
+
+/// Runs function with provided facts, returns facts known when it finished
+pub(crate) fn scope_with<F, R>(facts: Facts, f: F) -> (R, Facts)
+where
+    F: FnOnce() -> R,
+{
+    let previous = FACTS.with(|current| current.replace(facts));
+    let result = f();
+    (result, FACTS.with(|current| current.replace(previous)))
+}
+
+/// Stores all provided facts for current run
+pub(crate) fn merge(facts: Facts) {
+    FACTS.with(|current| current.borrow_mut().0.extend(facts.0));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_facts() {
+        set_fact("outer", "1");
+        scope(|| {
+            assert_eq!(get_fact("outer"), None);
+            set_fact("ip", "127.0.0.1");
+            set_fact("ip", "10.0.0.1");
+            assert_eq!(get_fact("ip"), Some("10.0.0.1".to_owned()));
+            with_facts(|facts| assert_eq!(facts.get("ip"), Some("10.0.0.1")));
+        });
+        assert_eq!(get_fact("ip"), None);
+        assert_eq!(get_fact("outer"), Some("1".to_owned()));
+    }
+}
@@ -1,4 +1,51 @@
-pub trait Check {
+use std::path::{Path, PathBuf};
+
+/// Execution context passed to [Check::yes_in] and [Action::run_in], carries
+/// working directory used to resolve relative paths (see
+/// [dir](crate::dir_context::dir)). Default context has no directory, current
+/// directory of process is used
+///
+/// Current directory of process is not changed, checks and actions have to
+/// resolve relative paths with [ExecContext::resolve] (closures used as checks
+/// or actions are run with [in_dir](crate::dir_context::in_dir) instead)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecContext {
+    dir: Option<PathBuf>,
+}
+
+impl ExecContext {
+    /// Working directory of context, [None] if current directory of process is
+    /// used
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Resolves relative path against working directory of context, absolute
+    /// paths are returned as is
+    pub fn resolve<P>(&self, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        match &self.dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Nested context with provided working directory, relative `dir` is
+    /// resolved against working directory of this context
+    pub fn with_dir<P>(&self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            dir: Some(self.resolve(dir)),
+        }
+    }
+}
+
+pub trait Check: Send + Sync {
     /// Short name of [Check]
     fn name(&self) -> &str;
     /// Performs check and returns [true] in case of success, [false] - if check
@@ -8,7 +55,24 @@ pub trait Check {
     /// [false] if not enough permission. Because of that there is no point in
     /// negating check, for example it is incorrect to use `not(is_dir)` need to
     /// use `path_is_missing`.
-    fn yes(&self) -> bool;
+    fn yes(&self) -> bool {
+        self.yes_explained().0
+    }
+    /// Same as [Check::yes], but also returns reason why check is *no* (eg.
+    /// how many times pattern was found)
+    fn yes_explained(&self) -> (bool, Option<String>) {
+        self.yes_in(&ExecContext::default())
+    }
+    /// Same as [Check::yes_explained], but runs check in provided context,
+    /// relative paths are resolved with [ExecContext::resolve] and wrapped
+    /// checks get the same context. The only method checks implement, others
+    /// call it with default context
+    fn yes_in(&self, ctx: &ExecContext) -> (bool, Option<String>);
+    /// Name of type of [Check], same as [Check::name] unless check is renamed
+    /// (see [check](crate::checks::check)), used to validate playbooks
+    fn kind(&self) -> &str {
+        self.name()
+    }
     fn into_check(self) -> Box<dyn Check>;
 }
 
@@ -42,11 +106,41 @@ impl From<Result<(), ()>> for ActionResult {
 }
 
 // todo: maybe add Instruction trait, and Action is just Instruction with no checks
-pub trait Action {
+pub trait Action: Send + Sync {
     /// Short name of [Action]
     fn name(&self) -> &str;
     /// Run action, return status if it succeed or failed
-    fn run(&self) -> ActionResult;
+    fn run(&self) -> ActionResult {
+        self.run_explained().0
+    }
+    /// Same as [Action::run], but also returns reason of failure (eg. exit
+    /// status and stderr of command)
+    fn run_explained(&self) -> (ActionResult, Option<String>) {
+        self.run_in(&ExecContext::default())
+    }
+    /// Same as [Action::run_explained], but runs action in provided context,
+    /// relative paths are resolved with [ExecContext::resolve] and wrapped
+    /// actions get the same context. The only method actions implement to
+    /// run, others call it with default context
+    fn run_in(&self, ctx: &ExecContext) -> (ActionResult, Option<String>);
+    /// Prepares action which reverts changes made by [Action::run], need to be
+    /// called right before `run` (it remembers current state of system),
+    /// returns [None] if action can't be undone
+    fn undo(&self) -> Option<Box<dyn Action>> {
+        self.undo_in(&ExecContext::default())
+    }
+    /// Same as [Action::undo] for action run with [Action::run_in], returned
+    /// action does not depend on context, actions which can be undone
+    /// implement it, by default there is no undo
+    fn undo_in(&self, _ctx: &ExecContext) -> Option<Box<dyn Action>> {
+        None
+    }
+    /// Name of type of [Action], same as [Action::name] unless action is
+    /// renamed (see [action](crate::actions::action)), used to validate
+    /// playbooks
+    fn kind(&self) -> &str {
+        self.name()
+    }
     fn into_action(self) -> Box<dyn Action>;
 }
 
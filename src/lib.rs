@@ -1,19 +1,26 @@
 pub mod actions;
+pub mod backup;
 pub mod checks;
 mod cli;
 pub mod dgraph;
 pub mod dir_context;
+pub mod facts;
 pub mod instructions;
 pub mod interfaces;
+pub mod lines;
 pub mod list_builder;
+pub mod observer;
 pub mod pattern;
 pub mod playbook;
 pub mod process;
+pub mod report;
 pub mod search;
 mod story_formatter;
+pub mod template;
 
 pub use cli::{run_cli, run_cli_with_input};
-pub use playbook::{instruction, Playbook};
+pub use playbook::{group, instruction, Playbook};
+pub use story_formatter::OutputFormat;
 
 #[cfg(test)]
 mod tests {
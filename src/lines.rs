@@ -0,0 +1,345 @@
+//! Line based editing of text files (see
+//! [ensure_line](crate::actions::ensure_line) and
+//! [ensure_block](crate::actions::ensure_block))
+
+use std::ops::Range;
+
+use crate::pattern::Pattern;
+
+/// Where line is inserted if no line of file matches pattern
+#[derive(Clone, Default)]
+pub enum LinePosition {
+    /// After last line of file
+    #[default]
+    End,
+    /// Before first line of file
+    Start,
+    /// After last line matching pattern, at the end if no line matches
+    After(Pattern),
+    /// Before first line matching pattern, at the end if no line matches
+    Before(Pattern),
+}
+
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut lines: Vec<&[u8]> = content.split(|b| *b == b'\n').collect();
+    if content.is_empty() || content.ends_with(b"\n") {
+        lines.pop();
+    }
+    lines
+}
+
+/// Joins lines back, content is returned as is if lines are not changed
+fn join_lines(content: &[u8], lines: Vec<&[u8]>) -> Vec<u8> {
+    if lines == split_lines(content) {
+        return content.to_vec();
+    }
+    let mut joined = lines.join(&b'\n');
+    if !joined.is_empty() {
+        joined.push(b'\n');
+    }
+    joined
+}
+
+fn is_match(pattern: &Pattern, line: &[u8]) -> bool {
+    pattern.contains(line).unwrap_or(false)
+}
+
+/// Number of lines matching pattern
+pub fn count_lines(content: &[u8], pattern: &Pattern) -> usize {
+    split_lines(content)
+        .into_iter()
+        .filter(|l| is_match(pattern, l))
+        .count()
+}
+
+/// Replaces first line matching `pattern` with `line` and removes other
+/// matching lines (so setting is defined once), if no line matches and `line`
+/// is not in content it is inserted at `position`
+pub fn ensure_line(
+    content: &[u8],
+    line: &[u8],
+    pattern: &Pattern,
+    position: &LinePosition,
+) -> Vec<u8> {
+    let mut lines = split_lines(content);
+    if let Some(first) = lines.iter().position(|l| is_match(pattern, l)) {
+        let mut i = 0;
+        lines.retain(|l| {
+            i += 1;
+            i - 1 == first || !is_match(pattern, l)
+        });
+        lines[first] = line;
+        return join_lines(content, lines);
+    }
+    if lines.contains(&line) {
+        return content.to_vec();
+    }
+    let index = match position {
+        LinePosition::End => None,
+        LinePosition::Start => Some(0),
+        LinePosition::After(anchor) => lines
+            .iter()
+            .rposition(|l| is_match(anchor, l))
+            .map(|i| i + 1),
+        LinePosition::Before(anchor) => lines.iter().position(|l| is_match(anchor, l)),
+    };
+    lines.insert(index.unwrap_or(lines.len()), line);
+    join_lines(content, lines)
+}
+
+/// Removes all lines matching pattern
+pub fn remove_lines(content: &[u8], pattern: &Pattern) -> Vec<u8> {
+    let mut lines = split_lines(content);
+    lines.retain(|l| !is_match(pattern, l));
+    join_lines(content, lines)
+}
+
+/// Lines marking start and end of block with provided id
+fn block_markers(id: &str) -> (Vec<u8>, Vec<u8>) {
+    (
+        format!("# BEGIN pass {id}").into_bytes(),
+        format!("# END pass {id}").into_bytes(),
+    )
+}
+
+/// Line without trailing `\r` of CRLF line ending
+fn trim_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Lines of block including markers, [None] if there is no block
+fn find_block(lines: &[&[u8]], id: &str) -> Result<Option<Range<usize>>, String> {
+    let (begin, end) = block_markers(id);
+    let find = |marker: &[u8], from: usize| {
+        lines[from..]
+            .iter()
+            .position(|l| trim_cr(l) == marker)
+            .map(|i| i + from)
+    };
+    match find(&begin, 0) {
+        Some(begin_at) => match find(&end, begin_at + 1) {
+            Some(end_at) => Ok(Some(begin_at..end_at + 1)),
+            None => Err(format!("end marker of block `{id}` not found")),
+        },
+        None if find(&end, 0).is_some() => Err(format!("begin marker of block `{id}` not found")),
+        None => Ok(None),
+    }
+}
+
+/// Text of block including markers, lines end with `\r\n` if `crlf` is set
+fn block_text(id: &str, block: &[u8], crlf: bool) -> Vec<u8> {
+    let (begin, end) = block_markers(id);
+    let mut lines = vec![begin.as_slice()];
+    lines.extend(split_lines(block).into_iter().map(trim_cr));
+    lines.push(&end);
+    let separator: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+    let mut text = lines.join(separator);
+    text.extend_from_slice(separator);
+    text
+}
+
+/// Replaces block with provided id (lines between `# BEGIN pass <id>` and
+/// `# END pass <id>` markers) or appends it if there is no such block, fails
+/// if only one of markers is found. Block uses CRLF line endings if its begin
+/// marker (or first line of file for new block) does
+pub fn ensure_block(content: &[u8], id: &str, block: &[u8]) -> Result<Vec<u8>, String> {
+    let mut lines = split_lines(content);
+    let range = find_block(&lines, id)?.unwrap_or(lines.len()..lines.len());
+    let crlf = lines
+        .get(range.start)
+        .or(lines.first())
+        .is_some_and(|l| l.ends_with(b"\r"));
+    let text = block_text(id, block, crlf);
+    lines.splice(range, split_lines(&text));
+    Ok(join_lines(content, lines))
+}
+
+/// Removes block with provided id including its markers (see [ensure_block])
+pub fn remove_block(content: &[u8], id: &str) -> Result<Vec<u8>, String> {
+    let mut lines = split_lines(content);
+    if let Some(range) = find_block(&lines, id)? {
+        lines.drain(range);
+    }
+    Ok(join_lines(content, lines))
+}
+
+/// Checks if content has block with provided id, fails if only one of
+/// markers is found
+pub fn has_block(content: &[u8], id: &str) -> Result<bool, String> {
+    Ok(find_block(&split_lines(content), id)?.is_some())
+}
+
+/// Checks if content has block with provided id, returns reason if it does
+/// not
+pub fn explain_no_block(content: &[u8], id: &str, block: &[u8]) -> Option<String> {
+    match find_block(&split_lines(content), id) {
+        Err(reason) => Some(reason),
+        Ok(None) => Some("block not found".to_owned()),
+        Ok(Some(_)) if ensure_block(content, id, block).ok()? == content => None,
+        Ok(Some(_)) => Some("block differs".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pattern::re;
+
+    #[test]
+    fn test_ensure_line() {
+        let pattern = re("^#?PermitRootLogin ");
+        let ensure = |content: &str, position: LinePosition| {
+            let result = ensure_line(
+                content.as_bytes(),
+                b"PermitRootLogin no",
+                &pattern,
+                &position,
+            );
+            String::from_utf8(result).unwrap()
+        };
+        // replaced in place, duplicates removed
+        assert_eq!(
+            ensure(
+                "Port 22\n#PermitRootLogin yes\nX 1\nPermitRootLogin yes\n",
+                LinePosition::End
+            ),
+            "Port 22\nPermitRootLogin no\nX 1\n"
+        );
+        // unchanged content is kept as is
+        assert_eq!(
+            ensure("PermitRootLogin no\nPort 22", LinePosition::End),
+            "PermitRootLogin no\nPort 22"
+        );
+        assert_eq!(ensure("", LinePosition::End), "PermitRootLogin no\n");
+        assert_eq!(
+            ensure("Port 22", LinePosition::End),
+            "Port 22\nPermitRootLogin no\n"
+        );
+        assert_eq!(
+            ensure("Port 22\n", LinePosition::Start),
+            "PermitRootLogin no\nPort 22\n"
+        );
+        let after = LinePosition::After(re("^Port"));
+        assert_eq!(
+            ensure("Port 22\nPort 23\nX 1\n", after.clone()),
+            "Port 22\nPort 23\nPermitRootLogin no\nX 1\n"
+        );
+        let before = LinePosition::Before(re("^X"));
+        assert_eq!(
+            ensure("Port 22\nX 1\nX 2\n", before),
+            "Port 22\nPermitRootLogin no\nX 1\nX 2\n"
+        );
+        assert_eq!(ensure("A 1\n", after), "A 1\nPermitRootLogin no\n");
+        // line not matched by pattern is not duplicated
+        assert_eq!(
+            ensure_line(b"a\nb\n", b"b", &re("^c"), &LinePosition::Start),
+            b"a\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_lines() {
+        let pattern = re("^PermitRootLogin ");
+        assert_eq!(
+            remove_lines(
+                b"PermitRootLogin yes\nPort 22\nPermitRootLogin no\n",
+                &pattern
+            ),
+            b"Port 22\n"
+        );
+        assert_eq!(remove_lines(b"Port 22", &pattern), b"Port 22");
+        assert_eq!(remove_lines(b"PermitRootLogin yes\n", &pattern), b"");
+        assert_eq!(count_lines(b"a\nab\nb\n", &"a".into()), 2);
+    }
+
+    #[test]
+    fn test_block() {
+        let hosts = "127.0.0.1 localhost\n";
+        let with_block = "127.0.0.1 localhost\n# BEGIN pass db\n10.0.0.2 db\n# END pass db\n";
+        let ensure = |content: &str, block: &str| {
+            ensure_block(content.as_bytes(), "db", block.as_bytes())
+                .map(|c| String::from_utf8(c).unwrap())
+        };
+        assert_eq!(ensure(hosts, "10.0.0.2 db"), Ok(with_block.to_owned()));
+        assert_eq!(
+            ensure(with_block, "10.0.0.2 db\n"),
+            Ok(with_block.to_owned())
+        );
+        assert_eq!(
+            ensure(&format!("{with_block}# other\n"), "10.0.0.3 db\n10.0.0.4 db2"),
+            Ok("127.0.0.1 localhost\n# BEGIN pass db\n10.0.0.3 db\n10.0.0.4 db2\n# END pass db\n# other\n".to_owned())
+        );
+        assert_eq!(
+            ensure("# END pass db\n", ""),
+            Err("begin marker of block `db` not found".to_owned())
+        );
+        assert_eq!(
+            ensure("# BEGIN pass db\n", ""),
+            Err("end marker of block `db` not found".to_owned())
+        );
+        assert_eq!(
+            remove_block(with_block.as_bytes(), "db"),
+            Ok(hosts.as_bytes().to_vec())
+        );
+        assert_eq!(
+            remove_block(hosts.as_bytes(), "db"),
+            Ok(hosts.as_bytes().to_vec())
+        );
+        assert_eq!(
+            remove_block(with_block.as_bytes(), "other"),
+            Ok(with_block.as_bytes().to_vec())
+        );
+        assert_eq!(
+            explain_no_block(with_block.as_bytes(), "db", b"10.0.0.2 db"),
+            None
+        );
+        assert_eq!(
+            explain_no_block(with_block.as_bytes(), "db", b"10.0.0.3 db").as_deref(),
+            Some("block differs")
+        );
+        assert_eq!(
+            explain_no_block(hosts.as_bytes(), "db", b"").as_deref(),
+            Some("block not found")
+        );
+        assert_eq!(has_block(with_block.as_bytes(), "db"), Ok(true));
+        assert_eq!(has_block(hosts.as_bytes(), "db"), Ok(false));
+    }
+
+    #[test]
+    fn test_block_crlf() {
+        let hosts = "127.0.0.1 localhost\r\n";
+        let with_block =
+            "127.0.0.1 localhost\r\n# BEGIN pass db\r\n10.0.0.2 db\r\n# END pass db\r\n";
+        let ensure = |content: &str, block: &str| {
+            ensure_block(content.as_bytes(), "db", block.as_bytes())
+                .map(|c| String::from_utf8(c).unwrap())
+        };
+        assert_eq!(ensure(hosts, "10.0.0.2 db\n"), Ok(with_block.to_owned()));
+        // block is found, not appended again
+        assert_eq!(
+            ensure(with_block, "10.0.0.2 db\r\n"),
+            Ok(with_block.to_owned())
+        );
+        assert_eq!(
+            explain_no_block(with_block.as_bytes(), "db", b"10.0.0.2 db"),
+            None
+        );
+        assert_eq!(
+            remove_block(with_block.as_bytes(), "db"),
+            Ok(hosts.as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_block_stray_end() {
+        let content = "# END pass db\n# BEGIN pass db\nold\n# END pass db\n";
+        assert_eq!(
+            ensure_block(content.as_bytes(), "db", b"new"),
+            Ok(b"# END pass db\n# BEGIN pass db\nnew\n# END pass db\n".to_vec())
+        );
+        assert_eq!(
+            ensure_block(b"# END pass db\n# BEGIN pass db\n", "db", b""),
+            Err("end marker of block `db` not found".to_owned())
+        );
+    }
+}
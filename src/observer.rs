@@ -0,0 +1,830 @@
+//! Hooks into playbook execution
+
+use std::time::Duration;
+
+use crate::{
+    interfaces::ActionResult,
+    process::ProcessLog,
+    report::{ApplyReport, InstructionReport, SkipReason},
+    story_formatter::{SectionKind, StoryFormatter},
+    OutputFormat,
+};
+
+/// Group of checks which are evaluated together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStage {
+    /// Environment checks of playbook
+    PlaybookEnv,
+    /// Conditions of instruction, evaluated first
+    Condition,
+    /// Environment checks of instruction
+    Env,
+    /// Confirmation checks of instruction before action
+    ConfirmBefore,
+    /// Confirmation checks of instruction after action
+    ConfirmAfter,
+    /// Confirmation checks of instruction evaluated again after failed
+    /// attempt, before retrying action
+    ConfirmRetry,
+}
+
+/// Receives events while playbook is applied, all methods do nothing by
+/// default. Indexes of instructions and checks start from `0`.
+pub trait PlaybookObserver {
+    fn on_playbook_start(&mut self, _name: &str) {}
+
+    fn on_instruction_start(&mut self, _index: usize, _name: &str) {}
+
+    /// Called instead of [PlaybookObserver::on_instruction_start] for
+    /// instruction which is skipped
+    fn on_instruction_skipped(&mut self, _index: usize, _name: &str, _reason: SkipReason) {}
+
+    /// Called before group of checks is evaluated, not called for empty group
+    fn on_checks_start(&mut self, _stage: CheckStage) {}
+
+    /// `reason` explains why check is *no* (see
+    /// [Check::yes_explained](crate::interfaces::Check::yes_explained))
+    fn on_check_result(
+        &mut self,
+        _stage: CheckStage,
+        _index: usize,
+        _name: &str,
+        _yes: bool,
+        _reason: Option<&str>,
+    ) {
+    }
+
+    /// Called after all checks of group are evaluated. For
+    /// [CheckStage::ConfirmBefore] `ok` is `true` if checks are not mixed
+    /// (all *yes* or all *no*), for other stages if all checks are *yes*
+    /// (for [CheckStage::ConfirmRetry] it means action will not be retried,
+    /// for [CheckStage::Condition] that instruction is not skipped).
+    fn on_checks_finished(&mut self, _stage: CheckStage, _ok: bool) {}
+
+    fn on_action_start(&mut self, _index: usize, _name: &str) {}
+
+    /// Called instead of [PlaybookObserver::on_action_start] for group of
+    /// instructions, instructions of group are reported with their indexes
+    /// inside of group until [PlaybookObserver::on_group_finished]
+    fn on_group_start(&mut self, _index: usize, _name: &str) {}
+
+    fn on_group_finished(&mut self, _index: usize, _name: &str, _ok: bool) {}
+
+    /// `reason` explains why action failed (see
+    /// [Action::run_explained](crate::interfaces::Action::run_explained))
+    fn on_action_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        _result: ActionResult,
+        _reason: Option<&str>,
+        _duration: Duration,
+    ) {
+    }
+
+    /// Called after failed attempt, before waiting `delay` and running action
+    /// again, `attempt` is number of next attempt (starting from `2`)
+    fn on_retry(&mut self, _index: usize, _attempt: usize, _attempts: usize, _delay: Duration) {}
+
+    /// Called right before action is run, if output of external processes is
+    /// printed while they run (see
+    /// [ExternalOutput::Live](crate::playbook::ExternalOutput::Live))
+    fn on_live_output(&mut self, _index: usize, _name: &str) {}
+
+    /// Called after [PlaybookObserver::on_action_finished] with output of
+    /// external processes run by failed action (see
+    /// [ExternalOutput::OnFailure](crate::playbook::ExternalOutput::OnFailure))
+    fn on_external_output(&mut self, _index: usize, _name: &str, _output: &[ProcessLog]) {}
+
+    /// Called for every instruction which was started
+    fn on_instruction_finished(&mut self, _index: usize, _report: &InstructionReport) {}
+
+    /// Called before first notified handler is run
+    fn on_handlers_start(&mut self) {}
+
+    fn on_handler_start(&mut self, _index: usize, _name: &str) {}
+
+    fn on_handler_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        _result: ActionResult,
+        _reason: Option<&str>,
+    ) {
+    }
+
+    fn on_handlers_finished(&mut self, _ok: bool) {}
+
+    /// Called when playbook failed and some of applied instructions will be
+    /// reverted
+    fn on_rollback_start(&mut self) {}
+
+    fn on_rollback_step_start(&mut self, _index: usize, _name: &str) {}
+
+    /// `reason` explains why rollback action failed (see
+    /// [Action::run_explained](crate::interfaces::Action::run_explained))
+    fn on_rollback_step_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        _result: ActionResult,
+        _reason: Option<&str>,
+    ) {
+    }
+
+    fn on_rollback_finished(&mut self, _ok: bool) {}
+
+    fn on_playbook_finished(&mut self, _report: &ApplyReport) {}
+}
+
+/// Passes events to every observer in order
+impl PlaybookObserver for Vec<Box<dyn PlaybookObserver>> {
+    fn on_playbook_start(&mut self, name: &str) {
+        for o in self.iter_mut() {
+            o.on_playbook_start(name);
+        }
+    }
+
+    fn on_instruction_start(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_instruction_start(index, name);
+        }
+    }
+
+    fn on_instruction_skipped(&mut self, index: usize, name: &str, reason: SkipReason) {
+        for o in self.iter_mut() {
+            o.on_instruction_skipped(index, name, reason);
+        }
+    }
+
+    fn on_checks_start(&mut self, stage: CheckStage) {
+        for o in self.iter_mut() {
+            o.on_checks_start(stage);
+        }
+    }
+
+    fn on_check_result(
+        &mut self,
+        stage: CheckStage,
+        index: usize,
+        name: &str,
+        yes: bool,
+        reason: Option<&str>,
+    ) {
+        for o in self.iter_mut() {
+            o.on_check_result(stage, index, name, yes, reason);
+        }
+    }
+
+    fn on_checks_finished(&mut self, stage: CheckStage, ok: bool) {
+        for o in self.iter_mut() {
+            o.on_checks_finished(stage, ok);
+        }
+    }
+
+    fn on_action_start(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_action_start(index, name);
+        }
+    }
+
+    fn on_action_finished(
+        &mut self,
+        index: usize,
+        name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+        duration: Duration,
+    ) {
+        for o in self.iter_mut() {
+            o.on_action_finished(index, name, result, reason, duration);
+        }
+    }
+
+    fn on_group_start(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_group_start(index, name);
+        }
+    }
+
+    fn on_group_finished(&mut self, index: usize, name: &str, ok: bool) {
+        for o in self.iter_mut() {
+            o.on_group_finished(index, name, ok);
+        }
+    }
+
+    fn on_retry(&mut self, index: usize, attempt: usize, attempts: usize, delay: Duration) {
+        for o in self.iter_mut() {
+            o.on_retry(index, attempt, attempts, delay);
+        }
+    }
+
+    fn on_live_output(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_live_output(index, name);
+        }
+    }
+
+    fn on_external_output(&mut self, index: usize, name: &str, output: &[ProcessLog]) {
+        for o in self.iter_mut() {
+            o.on_external_output(index, name, output);
+        }
+    }
+
+    fn on_instruction_finished(&mut self, index: usize, report: &InstructionReport) {
+        for o in self.iter_mut() {
+            o.on_instruction_finished(index, report);
+        }
+    }
+
+    fn on_handlers_start(&mut self) {
+        for o in self.iter_mut() {
+            o.on_handlers_start();
+        }
+    }
+
+    fn on_handler_start(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_handler_start(index, name);
+        }
+    }
+
+    fn on_handler_finished(
+        &mut self,
+        index: usize,
+        name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+    ) {
+        for o in self.iter_mut() {
+            o.on_handler_finished(index, name, result, reason);
+        }
+    }
+
+    fn on_handlers_finished(&mut self, ok: bool) {
+        for o in self.iter_mut() {
+            o.on_handlers_finished(ok);
+        }
+    }
+
+    fn on_rollback_start(&mut self) {
+        for o in self.iter_mut() {
+            o.on_rollback_start();
+        }
+    }
+
+    fn on_rollback_step_start(&mut self, index: usize, name: &str) {
+        for o in self.iter_mut() {
+            o.on_rollback_step_start(index, name);
+        }
+    }
+
+    fn on_rollback_step_finished(
+        &mut self,
+        index: usize,
+        name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+    ) {
+        for o in self.iter_mut() {
+            o.on_rollback_step_finished(index, name, result, reason);
+        }
+    }
+
+    fn on_rollback_finished(&mut self, ok: bool) {
+        for o in self.iter_mut() {
+            o.on_rollback_finished(ok);
+        }
+    }
+
+    fn on_playbook_finished(&mut self, report: &ApplyReport) {
+        for o in self.iter_mut() {
+            o.on_playbook_finished(report);
+        }
+    }
+}
+
+/// Event of single instruction recorded by [EventBuffer]
+enum Event {
+    InstructionStart(usize, String),
+    InstructionSkipped(usize, String, SkipReason),
+    ChecksStart(CheckStage),
+    CheckResult(CheckStage, usize, String, bool, Option<String>),
+    ChecksFinished(CheckStage, bool),
+    ActionStart(usize, String),
+    ActionFinished(usize, String, ActionResult, Option<String>, Duration),
+    Retry(usize, usize, usize, Duration),
+    LiveOutput(usize, String),
+    ExternalOutput(usize, String, Vec<ProcessLog>),
+    GroupStart(usize, String),
+    GroupFinished(usize, String, bool),
+    InstructionFinished(usize, Box<InstructionReport>),
+}
+
+/// Records events of instructions applied in another thread, so they can be
+/// passed to observer later without mixing with events of other instructions
+#[derive(Default)]
+pub(crate) struct EventBuffer {
+    events: Vec<Event>,
+}
+
+impl EventBuffer {
+    /// Passes all recorded events to observer in order
+    pub fn replay(self, observer: &mut dyn PlaybookObserver) {
+        for event in self.events {
+            match event {
+                Event::InstructionStart(index, name) => observer.on_instruction_start(index, &name),
+                Event::InstructionSkipped(index, name, reason) => {
+                    observer.on_instruction_skipped(index, &name, reason)
+                }
+                Event::ChecksStart(stage) => observer.on_checks_start(stage),
+                Event::CheckResult(stage, index, name, yes, reason) => {
+                    observer.on_check_result(stage, index, &name, yes, reason.as_deref())
+                }
+                Event::ChecksFinished(stage, ok) => observer.on_checks_finished(stage, ok),
+                Event::ActionStart(index, name) => observer.on_action_start(index, &name),
+                Event::ActionFinished(index, name, result, reason, duration) => {
+                    observer.on_action_finished(index, &name, result, reason.as_deref(), duration)
+                }
+                Event::Retry(index, attempt, attempts, delay) => {
+                    observer.on_retry(index, attempt, attempts, delay)
+                }
+                Event::LiveOutput(index, name) => observer.on_live_output(index, &name),
+                Event::ExternalOutput(index, name, output) => {
+                    observer.on_external_output(index, &name, &output)
+                }
+                Event::GroupStart(index, name) => observer.on_group_start(index, &name),
+                Event::GroupFinished(index, name, ok) => {
+                    observer.on_group_finished(index, &name, ok)
+                }
+                Event::InstructionFinished(index, report) => {
+                    observer.on_instruction_finished(index, &report)
+                }
+            }
+        }
+    }
+}
+
+impl PlaybookObserver for EventBuffer {
+    fn on_instruction_start(&mut self, index: usize, name: &str) {
+        self.events
+            .push(Event::InstructionStart(index, name.to_owned()));
+    }
+
+    fn on_instruction_skipped(&mut self, index: usize, name: &str, reason: SkipReason) {
+        self.events
+            .push(Event::InstructionSkipped(index, name.to_owned(), reason));
+    }
+
+    fn on_checks_start(&mut self, stage: CheckStage) {
+        self.events.push(Event::ChecksStart(stage));
+    }
+
+    fn on_check_result(
+        &mut self,
+        stage: CheckStage,
+        index: usize,
+        name: &str,
+        yes: bool,
+        reason: Option<&str>,
+    ) {
+        self.events.push(Event::CheckResult(
+            stage,
+            index,
+            name.to_owned(),
+            yes,
+            reason.map(|r| r.to_owned()),
+        ));
+    }
+
+    fn on_checks_finished(&mut self, stage: CheckStage, ok: bool) {
+        self.events.push(Event::ChecksFinished(stage, ok));
+    }
+
+    fn on_action_start(&mut self, index: usize, name: &str) {
+        self.events.push(Event::ActionStart(index, name.to_owned()));
+    }
+
+    fn on_action_finished(
+        &mut self,
+        index: usize,
+        name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+        duration: Duration,
+    ) {
+        self.events.push(Event::ActionFinished(
+            index,
+            name.to_owned(),
+            result,
+            reason.map(|r| r.to_owned()),
+            duration,
+        ));
+    }
+
+    fn on_retry(&mut self, index: usize, attempt: usize, attempts: usize, delay: Duration) {
+        self.events
+            .push(Event::Retry(index, attempt, attempts, delay));
+    }
+
+    fn on_live_output(&mut self, index: usize, name: &str) {
+        self.events.push(Event::LiveOutput(index, name.to_owned()));
+    }
+
+    fn on_external_output(&mut self, index: usize, name: &str, output: &[ProcessLog]) {
+        self.events.push(Event::ExternalOutput(
+            index,
+            name.to_owned(),
+            output.to_vec(),
+        ));
+    }
+
+    fn on_group_start(&mut self, index: usize, name: &str) {
+        self.events.push(Event::GroupStart(index, name.to_owned()));
+    }
+
+    fn on_group_finished(&mut self, index: usize, name: &str, ok: bool) {
+        self.events
+            .push(Event::GroupFinished(index, name.to_owned(), ok));
+    }
+
+    fn on_instruction_finished(&mut self, index: usize, report: &InstructionReport) {
+        self.events
+            .push(Event::InstructionFinished(index, Box::new(report.clone())));
+    }
+}
+
+/// Prints playbook story in terminal (as text or JSON lines), used by
+/// [Playbook::apply](crate::Playbook::apply)
+pub struct StoryObserver {
+    story: StoryFormatter,
+    name: String,
+    actions_open: bool,
+    /// Depths of open instruction sections, more than one for groups
+    instruction_depth: Vec<usize>,
+    pre_open: bool,
+    post_shown: bool,
+    confirm_before: Vec<(String, bool, Option<String>)>,
+}
+
+impl StoryObserver {
+    pub fn new(output: OutputFormat) -> Self {
+        Self {
+            story: StoryFormatter::new(output),
+            name: String::new(),
+            actions_open: false,
+            instruction_depth: vec![],
+            pre_open: false,
+            post_shown: false,
+            confirm_before: vec![],
+        }
+    }
+
+    fn close_pre(&mut self) {
+        if self.pre_open {
+            self.story.close(true);
+            self.pre_open = false;
+        }
+    }
+
+    fn open_actions(&mut self) {
+        // `Actions` of group is opened when group starts
+        if !self.actions_open && self.instruction_depth.is_empty() {
+            self.story.open("Actions", SectionKind::Section);
+            self.actions_open = true;
+        }
+    }
+}
+
+impl PlaybookObserver for StoryObserver {
+    fn on_playbook_start(&mut self, name: &str) {
+        self.name = name.to_owned();
+        self.story.playbook_header(name);
+        self.story.open("Playbook", SectionKind::Section);
+    }
+
+    fn on_instruction_start(&mut self, index: usize, name: &str) {
+        self.open_actions();
+        self.story
+            .open(format!("{}.{}", index + 1, name), SectionKind::Section);
+        self.instruction_depth.push(self.story.depth());
+        self.post_shown = false;
+        self.story.open("pre", SectionKind::Section);
+        self.pre_open = true;
+    }
+
+    fn on_instruction_skipped(&mut self, index: usize, name: &str, reason: SkipReason) {
+        self.open_actions();
+        self.story
+            .open(format!("{}.{}", index + 1, name), SectionKind::Section);
+        self.story.section_note(reason.describe());
+        self.story.close(true);
+    }
+
+    fn on_checks_start(&mut self, stage: CheckStage) {
+        match stage {
+            CheckStage::PlaybookEnv | CheckStage::Env => {
+                self.story.open("Environment", SectionKind::Checklist);
+            }
+            CheckStage::Condition => {
+                self.story.open("Condition", SectionKind::Checklist);
+            }
+            CheckStage::ConfirmBefore => {
+                self.confirm_before.clear();
+                self.story.open("Confirmation", SectionKind::Checklist);
+            }
+            CheckStage::ConfirmRetry => {
+                self.story.open("Confirmation", SectionKind::Checklist);
+            }
+            CheckStage::ConfirmAfter => {
+                self.post_shown = true;
+                self.story.open("post", SectionKind::Section);
+                self.story.open("Confirmation", SectionKind::Checklist);
+            }
+        }
+    }
+
+    fn on_check_result(
+        &mut self,
+        stage: CheckStage,
+        index: usize,
+        name: &str,
+        yes: bool,
+        reason: Option<&str>,
+    ) {
+        if stage == CheckStage::ConfirmBefore {
+            // printed when all confirmations are known
+            self.confirm_before
+                .push((name.to_owned(), yes, reason.map(|r| r.to_owned())));
+        } else {
+            self.story.checklist_item(yes, index + 1, name);
+            if let (false, Some(reason)) = (yes, reason) {
+                self.story.checklist_item_reason(reason);
+            }
+        }
+    }
+
+    fn on_checks_finished(&mut self, stage: CheckStage, ok: bool) {
+        if stage == CheckStage::ConfirmBefore {
+            let confirm_before = std::mem::take(&mut self.confirm_before);
+            let all_confirm_no = confirm_before.iter().all(|(_, yes, _)| !*yes);
+            if ok && all_confirm_no {
+                self.story
+                    .checklist_title_note("checking all confirmations is *no*");
+            }
+            for (i, (name, yes, reason)) in confirm_before.iter().enumerate() {
+                self.story.checklist_item(*yes, i + 1, name);
+                // *no* before action is expected, reason is shown only for
+                // mixed confirmations
+                if let (false, false, Some(reason)) = (ok, *yes, reason) {
+                    self.story.checklist_item_reason(reason);
+                }
+            }
+            if !ok {
+                self.story
+                    .checklist_note("confirmation checks should be *all yes* or *all no*");
+            } else if !all_confirm_no {
+                self.story
+                    .checklist_note("action already applied, skipping");
+            }
+        }
+        if stage == CheckStage::Condition {
+            if !ok {
+                self.story
+                    .checklist_note(SkipReason::ConditionNotMet.describe());
+            }
+            // condition not met is not failure
+            self.story.close(true);
+            return;
+        }
+        if stage == CheckStage::ConfirmRetry {
+            if ok {
+                self.story
+                    .checklist_note("action applied, no need to retry");
+            }
+            // evaluating checks before retry is not failure
+            self.story.close(true);
+            return;
+        }
+        self.story.close(ok);
+        if stage == CheckStage::ConfirmAfter {
+            // closing `post` section
+            self.story.close(ok);
+        }
+    }
+
+    fn on_action_start(&mut self, _index: usize, _name: &str) {
+        self.close_pre();
+        self.story.open("apply", SectionKind::Process);
+    }
+
+    fn on_group_start(&mut self, _index: usize, _name: &str) {
+        self.close_pre();
+        self.story.open("Actions", SectionKind::Section);
+    }
+
+    fn on_group_finished(&mut self, _index: usize, _name: &str, ok: bool) {
+        self.story.close(ok);
+        // state of instructions inside group is not related to group itself
+        self.pre_open = false;
+        self.post_shown = false;
+    }
+
+    fn on_action_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+        _duration: Duration,
+    ) {
+        self.story.close_with_reason(result.ok(), reason);
+    }
+
+    fn on_retry(&mut self, _index: usize, attempt: usize, attempts: usize, delay: Duration) {
+        self.story
+            .section_note(&format!("retry {attempt} of {attempts} in {delay:?}"));
+    }
+
+    fn on_live_output(&mut self, _index: usize, _name: &str) {
+        self.story.live_output();
+    }
+
+    fn on_external_output(&mut self, _index: usize, _name: &str, output: &[ProcessLog]) {
+        for process in output {
+            self.story.process_output(process);
+        }
+    }
+
+    fn on_instruction_finished(&mut self, _index: usize, report: &InstructionReport) {
+        self.pre_open = false;
+        let ok = report.result.map(|r| r.ok()).unwrap_or_default();
+        if report.ran && ok && !self.post_shown {
+            // action without confirmation checks still has `post` stage
+            self.story.open("post", SectionKind::Section);
+            self.story.close(true);
+        }
+        let depth = self.instruction_depth.pop().unwrap_or_default();
+        self.story.close_to(depth, ok);
+        // closing instruction section
+        self.story.close(ok);
+    }
+
+    fn on_handlers_start(&mut self) {
+        // all instructions are finished, leaving only `Playbook` open
+        self.story.close_to(1, true);
+        self.story.open("Handlers", SectionKind::Section);
+    }
+
+    fn on_handler_start(&mut self, index: usize, name: &str) {
+        self.story
+            .open(format!("{}.{}", index + 1, name), SectionKind::Process);
+    }
+
+    fn on_handler_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+    ) {
+        self.story.close_with_reason(result.ok(), reason);
+    }
+
+    fn on_handlers_finished(&mut self, ok: bool) {
+        self.story.close(ok);
+    }
+
+    fn on_rollback_start(&mut self) {
+        // rollback happens only after failure, leaving only `Playbook` open
+        self.story.close_to(1, false);
+        self.story.open("Rollback", SectionKind::Section);
+    }
+
+    fn on_rollback_step_start(&mut self, index: usize, name: &str) {
+        self.story
+            .open(format!("{}.{}", index + 1, name), SectionKind::Process);
+    }
+
+    fn on_rollback_step_finished(
+        &mut self,
+        _index: usize,
+        _name: &str,
+        result: ActionResult,
+        reason: Option<&str>,
+    ) {
+        self.story.close_with_reason(result.ok(), reason);
+    }
+
+    fn on_rollback_finished(&mut self, ok: bool) {
+        self.story.close(ok);
+    }
+
+    fn on_playbook_finished(&mut self, report: &ApplyReport) {
+        self.story.close_to(0, report.ok());
+        self.story.playbook_result(&self.name, report.ok());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        actions::{always_fail, always_ok},
+        checks::{always_no, always_yes},
+        instruction, Playbook,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Records names of received events
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl PlaybookObserver for Recorder {
+        fn on_playbook_start(&mut self, name: &str) {
+            self.0.borrow_mut().push(format!("start {name}"));
+        }
+
+        fn on_instruction_start(&mut self, index: usize, name: &str) {
+            self.0
+                .borrow_mut()
+                .push(format!("instruction {index} {name}"));
+        }
+
+        fn on_check_result(
+            &mut self,
+            stage: CheckStage,
+            index: usize,
+            name: &str,
+            yes: bool,
+            _reason: Option<&str>,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(format!("check {stage:?} {index} {name} {yes}"));
+        }
+
+        fn on_action_finished(
+            &mut self,
+            index: usize,
+            _name: &str,
+            result: ActionResult,
+            _reason: Option<&str>,
+            _duration: Duration,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(format!("action {index} {result:?}"));
+        }
+
+        fn on_instruction_finished(&mut self, index: usize, report: &InstructionReport) {
+            self.0
+                .borrow_mut()
+                .push(format!("finished {index} {:?}", report.result));
+        }
+
+        fn on_playbook_finished(&mut self, report: &ApplyReport) {
+            self.0.borrow_mut().push(format!("end {}", report.ok()));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut observers: Vec<Box<dyn PlaybookObserver>> = vec![
+            Box::new(Recorder(events.clone())),
+            Box::new(StoryObserver::new(OutputFormat::Text)),
+        ];
+        let report = Playbook::new(
+            "observed",
+            "",
+            [always_yes()],
+            [
+                instruction(always_ok()).confirm(always_yes()),
+                instruction(always_ok()).with_env(always_yes()),
+                instruction(always_fail()).confirm(always_no()),
+                instruction(always_ok()),
+            ],
+        )
+        .apply_with(&mut observers);
+        assert!(!report.ok());
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "start observed",
+                "check PlaybookEnv 0 AlwaysYes true",
+                "instruction 0 AlwaysOk",
+                "check ConfirmBefore 0 AlwaysYes true",
+                "finished 0 Some(Ok)",
+                "instruction 1 AlwaysOk",
+                "check Env 0 AlwaysYes true",
+                "action 1 Ok",
+                "finished 1 Some(Ok)",
+                "instruction 2 AlwaysFail",
+                "check ConfirmBefore 0 AlwaysNo false",
+                "action 2 Fail",
+                "finished 2 Some(Fail)",
+                "end false",
+            ]
+        );
+    }
+}
@@ -34,6 +34,28 @@ impl Pattern {
         }
     }
 
+    /// Number of non overlapping matches in data, [None] if regex is used and
+    /// data is not utf8 text, empty binary pattern is never matched
+    pub fn count(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Pattern::Bin(b) if b.is_empty() => Some(0),
+            Pattern::Bin(b) => Some(find_pattern_iter(data, b).count()),
+            Pattern::Regex(r) => {
+                let s = String::from_utf8(data.to_vec()).ok()?;
+                Some(r.find_iter(&s).count())
+            }
+        }
+    }
+
+    /// Explains why pattern is not matched exactly once in data
+    pub fn explain_not_once(&self, data: &[u8]) -> String {
+        match self.count(data) {
+            None => "data is not utf8 text".to_owned(),
+            Some(0) => "pattern not found".to_owned(),
+            Some(n) => format!("pattern matched {n} times"),
+        }
+    }
+
     pub fn replace_once(&self, data: &[u8], replacement: &[u8]) -> Option<Vec<u8>> {
         match self {
             Pattern::Bin(p) => {
@@ -114,4 +136,21 @@ mod test {
                 .is_none());
         }
     }
+
+    #[test]
+    fn test_pattern_count() {
+        let data = "111 123 333".as_bytes();
+        assert_eq!(Pattern::from("1").count(data), Some(4));
+        assert_eq!(Pattern::from("").count(data), Some(0));
+        assert_eq!(re("3+").count(data), Some(2));
+        assert_eq!(re("1").count(&[0xff]), None);
+        assert_eq!(
+            Pattern::from("3").explain_not_once(data),
+            "pattern matched 4 times"
+        );
+        assert_eq!(
+            Pattern::from("4").explain_not_once(data),
+            "pattern not found"
+        );
+    }
 }
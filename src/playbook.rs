@@ -1,10 +1,43 @@
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::actions::Backoff;
+use crate::backup::{self, BACKUP_DIR};
+use crate::dgraph::PROGRESS_DIR;
+use crate::facts;
 use crate::interfaces::{Action, ActionResult, Check};
-use crate::story_formatter::StoryFormatter;
+use crate::observer::{CheckStage, EventBuffer, PlaybookObserver, StoryObserver};
+use crate::process;
+use crate::report::{
+    ApplyReport, CheckReport, CheckResult, HandlerReport, InstructionCheck, InstructionReport,
+    InstructionStatus, Issue, Severity, SkipReason,
+};
+use crate::story_formatter::{OutputFormat, StoryFormatter};
 
 // note: currently using result for convinience instead of ActionResult, allows
 // using `?` operator, when it will be possible to overload it, need return
 // `ActionResult`, now Ok == ActionResult::Ok, Err == ActionResult::Fail
 
+/// What is done by [Instruction]
+enum Step {
+    Action(Box<dyn Action>),
+    /// Instructions applied as single step
+    Group {
+        name: String,
+        instructions: Vec<Instruction>,
+    },
+}
+
+/// Instruction which was run, used for rollback
+struct Applied<'a> {
+    /// Indexes of instruction and its groups
+    path: Vec<usize>,
+    instruction: &'a Instruction,
+    undo: Option<Box<dyn Action>>,
+}
+
 pub struct Instruction {
     // status of action depending on check
     // b - before action
@@ -24,20 +57,66 @@ pub struct Instruction {
     // | ok     |   T   | F | T |
     // | ok     |       | T |   | action skipped in this case
     // ^^^^^^^^^^^^^^^^^^^^^^^^^^
+    conditions: Vec<Box<dyn Check>>,
     env_checks: Vec<Box<dyn Check>>,
     confirm_checks: Vec<Box<dyn Check>>,
-    action: Box<dyn Action>,
+    step: Step,
+    on_fail: Option<Box<dyn Action>>,
+    attempts: usize,
+    backoff: Backoff,
+    notify: Vec<String>,
+    tags: Vec<String>,
+    depends_on: Vec<String>,
+    external_output: Option<ExternalOutput>,
+    backup: Option<bool>,
 }
 
 impl Instruction {
     pub fn new(action: Box<dyn Action>) -> Self {
+        Self::with_step(Step::Action(action))
+    }
+
+    /// Creates instruction applying group of instructions as single step,
+    /// group fails on first failed instruction
+    pub fn new_group<Name, Instructions>(name: Name, instructions: Instructions) -> Self
+    where
+        Name: Into<String>,
+        Instructions: Into<Vec<Instruction>>,
+    {
+        Self::with_step(Step::Group {
+            name: name.into(),
+            instructions: instructions.into(),
+        })
+    }
+
+    fn with_step(step: Step) -> Self {
         Self {
+            conditions: vec![],
             env_checks: vec![],
             confirm_checks: vec![],
-            action,
+            step,
+            on_fail: None,
+            attempts: 1,
+            backoff: Backoff::None,
+            notify: vec![],
+            tags: vec![],
+            depends_on: vec![],
+            external_output: None,
+            backup: None,
         }
     }
 
+    /// Sets conditions of instruction, they are checked before any other
+    /// checks, if some of them are *no* instruction is skipped (unlike
+    /// environment checks, which fail playbook)
+    pub fn when<Checks>(mut self, conditions: Checks) -> Self
+    where
+        Checks: Into<Vec<Box<dyn Check>>>,
+    {
+        self.conditions = conditions.into();
+        self
+    }
+
     pub fn with_env<Checks>(mut self, env: Checks) -> Self
     where
         Checks: Into<Vec<Box<dyn Check>>>,
@@ -53,22 +132,328 @@ impl Instruction {
         self.confirm_checks = confirm.into();
         self
     }
+
+    /// Name of action or group
+    pub fn name(&self) -> &str {
+        match &self.step {
+            Step::Action(action) => action.name(),
+            Step::Group { name, .. } => name,
+        }
+    }
+
+    /// Empty report for instruction and instructions of its group
+    fn report(&self) -> InstructionReport {
+        let mut report = InstructionReport::new(self.name());
+        if let Step::Group { instructions, .. } = &self.step {
+            report.instructions = instructions.iter().map(Instruction::report).collect();
+        }
+        report
+    }
+
+    /// Sets action which reverts this instruction, it is run during rollback
+    /// if action of instruction was run and playbook failed (on this or any
+    /// later instruction). Used instead of [Action::undo], for group it is
+    /// used instead of reverting instructions of group
+    pub fn on_fail(mut self, rollback: Box<dyn Action>) -> Self {
+        self.on_fail = Some(rollback);
+        self
+    }
+
+    /// Runs action again if it fails (or confirmation checks after it fail),
+    /// `attempts` is total number of runs. Before each retry confirmation
+    /// checks are evaluated again, if all of them are *yes* instruction is
+    /// considered applied. Not used for groups
+    pub fn retry(mut self, attempts: usize, backoff: Backoff) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Notifies handler of playbook (see [Playbook::with_handler]) if action
+    /// of instruction was run, can be used multiple times to notify several
+    /// handlers
+    pub fn notify<Name>(mut self, handler: Name) -> Self
+    where
+        Name: Into<String>,
+    {
+        self.notify.push(handler.into());
+        self
+    }
+
+    /// Adds dependency on another instruction of playbook with provided name
+    /// (see [Instruction::name]), when instructions are applied in parallel
+    /// (see [Playbook::with_workers]) instruction is started only after all
+    /// its dependencies are applied. Skipped dependency (by condition, tags or
+    /// resume) does not block instruction, failed one does. Circular
+    /// dependencies fail playbook before anything is applied. Can be used
+    /// multiple times
+    pub fn depends_on<Name>(mut self, instruction: Name) -> Self
+    where
+        Name: Into<String>,
+    {
+        self.depends_on.push(instruction.into());
+        self
+    }
+
+    /// Adds tag to instruction, tags are used to select instructions to apply
+    /// (see [TagFilter]), can be used multiple times
+    pub fn tag<Tag>(mut self, tag: Tag) -> Self
+    where
+        Tag: Into<String>,
+    {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Sets what is done with output of external processes run by action,
+    /// overriding [Playbook::with_external_output], for group it is used by
+    /// instructions of group which don't set it
+    pub fn external_output(mut self, external_output: ExternalOutput) -> Self {
+        self.external_output = Some(external_output);
+        self.inherit_external_output(external_output);
+        self
+    }
+
+    /// Sets external output for instructions of group which don't set it
+    fn inherit_external_output(&mut self, external_output: ExternalOutput) {
+        if let Step::Group { instructions, .. } = &mut self.step {
+            for instruction in instructions {
+                if instruction.external_output.is_none() {
+                    instruction.external_output = Some(external_output);
+                    instruction.inherit_external_output(external_output);
+                }
+            }
+        }
+    }
+
+    /// Enables or disables backups of files changed by action (see
+    /// [Playbook::with_backups]), overriding setting of playbook, for group it
+    /// is used by instructions of group which don't set it
+    pub fn backup(mut self, enabled: bool) -> Self {
+        self.backup = Some(enabled);
+        self.inherit_backup(enabled);
+        self
+    }
+
+    /// Sets backup for instructions of group which don't set it
+    fn inherit_backup(&mut self, enabled: bool) {
+        if let Step::Group { instructions, .. } = &mut self.step {
+            for instruction in instructions {
+                if instruction.backup.is_none() {
+                    instruction.backup = Some(enabled);
+                    instruction.inherit_backup(enabled);
+                }
+            }
+        }
+    }
 }
 
 pub fn instruction(action: Box<dyn Action>) -> Instruction {
     Instruction::new(action)
 }
 
+/// init [Instruction] with group of instructions, `env_checks` are checked
+/// before any instruction of group is applied
+pub fn group<Name, Checks, Instructions>(
+    name: Name,
+    env_checks: Checks,
+    instructions: Instructions,
+) -> Instruction
+where
+    Name: Into<String>,
+    Checks: Into<Vec<Box<dyn Check>>>,
+    Instructions: Into<Vec<Instruction>>,
+{
+    Instruction::new_group(name, instructions).with_env(env_checks)
+}
+
+/// Embeds playbook into another playbook as group (see [group]), only name,
+/// environment checks and instructions of playbook are used
+impl From<Playbook> for Instruction {
+    fn from(playbook: Playbook) -> Self {
+        group(playbook.name, playbook.env_checks, playbook.instructions)
+    }
+}
+
+/// Selects instructions of [Playbook] to apply, other instructions are skipped
+/// (indexes start from `0`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Start {
+    /// Apply all instructions
+    #[default]
+    First,
+    /// Start from instruction which failed during previous run (see
+    /// [Playbook::saved_progress]), or from first instruction if there is no
+    /// saved progress
+    Resume,
+    /// Start from instruction with provided index
+    From(usize),
+    /// Apply only instruction with provided index
+    Only(usize),
+}
+
+/// What is done with output (stdout and stderr) of external processes run by
+/// actions (see [run](crate::process::run)), output of processes run by checks
+/// is always hidden
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExternalOutput {
+    /// Output is used only by action itself
+    #[default]
+    Hide,
+    /// Output is printed into stderr while process is running, output of
+    /// instructions applied in parallel can be mixed
+    Live,
+    /// Output is shown in story if action fails
+    OnFailure,
+    /// Output is stored in report of instruction
+    Report,
+}
+
+/// Selects instructions of [Playbook] by their tags (see [Instruction::tag]),
+/// filtered out instructions are skipped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// If not empty, only instructions with any of these tags are selected
+    pub tags: Vec<String>,
+    /// Instructions with any of these tags are filtered out
+    pub skip_tags: Vec<String>,
+}
+
+impl TagFilter {
+    /// Returns `true` if instruction with provided tags is selected
+    pub fn matches(&self, tags: &[String]) -> bool {
+        (self.tags.is_empty() || tags.iter().any(|t| self.tags.contains(t)))
+            && !tags.iter().any(|t| self.skip_tags.contains(t))
+    }
+}
+
+/// Name of [Playbook] created without name
+const UNNAMED: &str = "?without_name?";
+/// Description of [Playbook] created without description
+const UNDESCRIBED: &str = "?Without description?";
+
+/// Indexes of instructions each instruction depends on (see
+/// [Instruction::depends_on])
+fn dependencies(instructions: &[Instruction]) -> Vec<Vec<usize>> {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            instructions
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| {
+                    *j != i && instruction.depends_on.iter().any(|d| d == other.name())
+                })
+                .map(|(j, _)| j)
+                .collect()
+        })
+        .collect()
+}
+
+/// Finds circular dependency, returns indexes of instructions forming it
+fn find_cycle(dependencies: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Visit {
+        New,
+        InPath,
+        Done,
+    }
+    fn visit(
+        i: usize,
+        dependencies: &[Vec<usize>],
+        visits: &mut [Visit],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        visits[i] = Visit::InPath;
+        path.push(i);
+        for &j in &dependencies[i] {
+            match visits[j] {
+                Visit::InPath => {
+                    let start = path.iter().position(|p| *p == j).unwrap_or_default();
+                    return Some(path[start..].to_vec());
+                }
+                Visit::New => {
+                    if let Some(cycle) = visit(j, dependencies, visits, path) {
+                        return Some(cycle);
+                    }
+                }
+                Visit::Done => {}
+            }
+        }
+        path.pop();
+        visits[i] = Visit::Done;
+        None
+    }
+    let mut visits = vec![Visit::New; dependencies.len()];
+    (0..dependencies.len()).find_map(|i| {
+        if visits[i] == Visit::New {
+            visit(i, dependencies, &mut visits, &mut vec![])
+        } else {
+            None
+        }
+    })
+}
+
+/// Describes circular dependency found by [find_cycle]
+fn describe_cycle(instructions: &[Instruction], cycle: &[usize]) -> String {
+    let names: Vec<&str> = cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|i| instructions[*i].name())
+        .collect();
+    format!("dependency cycle: {}", names.join(" -> "))
+}
+
+/// Returns `true` if name can be used as name of file
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && name != "."
+        && name != ".."
+        && !name.chars().any(|c| c == '/' || c.is_control())
+}
+
+/// Returns `true` if confirmation check of provided kind can't be *yes* after
+/// action of provided kind changed the same path
+fn contradicts(action: &str, check: &str) -> bool {
+    const REQUIRE_PATH: &[&str] = &[
+        "IsFile",
+        "IsDir",
+        "CanRead",
+        "CanWrite",
+        "IsFileContent",
+        "FileContainsOnce",
+        "IsRenderedTemplate",
+    ];
+    match action {
+        "DeleteFile" | "RemoveDir" => REQUIRE_PATH.contains(&check),
+        "WriteFile" | "WriteTemplate" | "CreateDir" | "CopyFile" => check == "PathIsMissing",
+        _ => false,
+    }
+}
+
 // todo: add "instruction on fail/on success/on finish" to Playbook, can be useful for reporting issues
-// todo: option to not hide stdout/err output of external processes (show_external_output: bool)
 pub struct Playbook {
-    // todo: check no spaces, allow only lowercase ascii, allow filename safe symbols?
-    /// short [Playbook] name, which can be used as unqiue [Playbook] id
+    /// short [Playbook] name, which can be used as unqiue [Playbook] id, it
+    /// is used as file name (see [Playbook::validate])
     pub name: &'static str,
     /// description of `Playbook`, explaining its purpose for user
     pub description: &'static str,
     env_checks: Vec<Box<dyn Check>>,
     instructions: Vec<Instruction>,
+    output: OutputFormat,
+    rollback: bool,
+    start: Start,
+    progress_dir: PathBuf,
+    handlers: Vec<(String, Box<dyn Action>)>,
+    filter: TagFilter,
+    workers: usize,
+    external_output: ExternalOutput,
+    backup: bool,
+    backup_dir: PathBuf,
+    /// Directory with backups of current run, reserved on first backup
+    backup_run: Mutex<Option<PathBuf>>,
 }
 
 impl Playbook {
@@ -82,13 +467,9 @@ impl Playbook {
         Checks: Into<Vec<Box<dyn Check>>>,
         Instructions: Into<Vec<Instruction>>,
     {
-        let name = if name.is_empty() {
-            "?without_name?"
-        } else {
-            name
-        };
+        let name = if name.is_empty() { UNNAMED } else { name };
         let description = if description.is_empty() {
-            "?Without description?"
+            UNDESCRIBED
         } else {
             description
         };
@@ -97,22 +478,201 @@ impl Playbook {
             description,
             env_checks: env_checks.into(),
             instructions: instructions.into(),
+            output: OutputFormat::default(),
+            rollback: false,
+            start: Start::default(),
+            progress_dir: PathBuf::from(PROGRESS_DIR),
+            handlers: vec![],
+            filter: TagFilter::default(),
+            workers: 1,
+            external_output: ExternalOutput::default(),
+            backup: false,
+            backup_dir: PathBuf::from(BACKUP_DIR),
+            backup_run: Mutex::new(None),
         }
     }
 
-    fn check_checks(
-        story: &StoryFormatter,
-        checks: &[Box<dyn Check>],
-        ok_is_true: bool,
-    ) -> Result<(), ()> {
+    /// Sets format of output printed while playbook is applied or checked
+    pub fn with_output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Enables automatic rollback, if playbook fails all applied instructions
+    /// are reverted in reverse order using [Action::undo] (or
+    /// [Instruction::on_fail] if it set). Without it only
+    /// [Instruction::on_fail] actions are used for rollback
+    pub fn with_rollback(mut self) -> Self {
+        self.rollback = true;
+        self
+    }
+
+    /// Sets instructions which are applied, skipped instructions are shown in
+    /// story and marked in report
+    pub fn with_start(mut self, start: Start) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Sets filter selecting instructions by tags, filtered out instructions
+    /// are skipped when playbook is applied or checked
+    pub fn with_filter(mut self, filter: TagFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Applies independent instructions concurrently using up to `workers`
+    /// threads, instruction is started after all instructions it depends on
+    /// (see [Instruction::depends_on]) are applied. Story of each instruction
+    /// is printed when it is finished, instructions inside of groups are
+    /// applied in order. With `1` worker (default) instructions are applied
+    /// one by one in order. Actions changing current directory of process (see
+    /// [SetDir](crate::actions::SetDir)) affect all workers
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets what is done with output of external processes run by actions of
+    /// instructions which don't set it (see [Instruction::external_output])
+    pub fn with_external_output(mut self, external_output: ExternalOutput) -> Self {
+        self.external_output = external_output;
+        self
+    }
+
+    /// Sets directory where progress of failed run is saved (by default
+    /// [PROGRESS_DIR]), progress is saved only if parent of directory exists
+    pub fn with_progress_dir<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.progress_dir = path.into();
+        self
+    }
+
+    /// Enables backups for instructions which don't set it (see
+    /// [Instruction::backup]), before file is changed, deleted or renamed by
+    /// action its content is copied into directory of current run (see
+    /// [Playbook::with_backup_dir])
+    pub fn with_backups(mut self) -> Self {
+        self.backup = true;
+        self
+    }
+
+    /// Sets directory where backups are saved (by default [BACKUP_DIR]), each
+    /// run of playbook saves them into `<dir>/<playbook name>/<time of run>`
+    pub fn with_backup_dir<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.backup_dir = path.into();
+        self
+    }
+
+    fn backups_path(&self) -> PathBuf {
+        self.backup_dir.join(self.name)
+    }
+
+    /// Directory for backups of current run, created on first call during run
+    fn backup_run_dir(&self) -> Result<PathBuf, String> {
+        let mut run_dir = self.backup_run.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(run_dir) = &*run_dir {
+            return Ok(run_dir.clone());
+        }
+        let new_run_dir = backup::new_run_dir(&self.backups_path())?;
+        *run_dir = Some(new_run_dir.clone());
+        Ok(new_run_dir)
+    }
+
+    /// Names of runs with saved backups, oldest first
+    pub fn backup_runs(&self) -> Vec<String> {
+        backup::runs(&self.backups_path())
+    }
+
+    /// Paths of files backed up during run
+    pub fn backup_files(&self, run: &str) -> Vec<PathBuf> {
+        if !is_file_name(run) {
+            return vec![];
+        }
+        backup::files(&self.backups_path().join(run))
+    }
+
+    /// Restores files backed up during run, returns restored paths
+    pub fn restore_backup(&self, run: &str) -> Result<Vec<PathBuf>, String> {
+        if !is_file_name(run) {
+            return Err(format!("incorrect name of run `{run}`"));
+        }
+        backup::restore(&self.backups_path().join(run))
+    }
+
+    /// Registers handler, its action is run once after all instructions are
+    /// applied, if action of at least one instruction notifying it (see
+    /// [Instruction::notify]) was run. Handlers are run in order of
+    /// registration, failed handler fails playbook
+    pub fn with_handler<Name>(mut self, name: Name, action: Box<dyn Action>) -> Self
+    where
+        Name: Into<String>,
+    {
+        self.handlers.push((name.into(), action));
+        self
+    }
+
+    /// Finds index of instruction by its number (as shown in story, starting
+    /// from `1`) or by name of its action
+    pub fn find_instruction(&self, number_or_name: &str) -> Option<usize> {
+        if let Ok(number) = number_or_name.parse::<usize>() {
+            return (1..=self.instructions.len())
+                .contains(&number)
+                .then(|| number - 1);
+        }
+        self.instructions
+            .iter()
+            .position(|i| i.name() == number_or_name)
+    }
+
+    fn progress_path(&self) -> PathBuf {
+        self.progress_dir.join(self.name)
+    }
+
+    /// Index of instruction which failed during previous run
+    pub fn saved_progress(&self) -> Option<usize> {
+        let number: usize = std::fs::read_to_string(self.progress_path())
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        (1..=self.instructions.len())
+            .contains(&number)
+            .then(|| number - 1)
+    }
+
+    fn save_progress(&self, index: usize) {
+        // progress is best effort, it is not saved if there is no place for it
+        let Some(parent) = self.progress_dir.parent() else {
+            return;
+        };
+        if !parent.is_dir() {
+            return;
+        }
+        if !self.progress_dir.is_dir() && std::fs::create_dir(&self.progress_dir).is_err() {
+            return;
+        }
+        let _ = std::fs::write(self.progress_path(), format!("{}\n", index + 1));
+    }
+
+    fn clear_progress(&self) {
+        let _ = std::fs::remove_file(self.progress_path());
+    }
+
+    fn check_checks(story: &mut StoryFormatter, checks: &[Box<dyn Check>]) -> Result<(), ()> {
         let mut ok = true;
         for (i, next_check) in checks.iter().enumerate() {
-            let mut check_ok = next_check.yes();
-            if !ok_is_true {
-                check_ok = !check_ok;
-            }
+            let (check_ok, reason) = next_check.yes_explained();
             ok = check_ok && ok;
             story.checklist_item(check_ok, i + 1, next_check.name());
+            if let (false, Some(reason)) = (check_ok, reason) {
+                story.checklist_item_reason(&reason);
+            }
         }
         if ok {
             Ok(())
@@ -121,115 +681,903 @@ impl Playbook {
         }
     }
 
-    fn print_check_results(story: &StoryFormatter, checks: &[(&str, bool)]) {
-        for (i, (name, yes)) in checks.iter().enumerate() {
+    fn print_check_results(story: &mut StoryFormatter, checks: &[(&str, bool, Option<String>)]) {
+        for (i, (name, yes, reason)) in checks.iter().enumerate() {
             story.checklist_item(*yes, i + 1, name);
+            if let (false, Some(reason)) = (yes, reason) {
+                story.checklist_item_reason(reason);
+            }
+        }
+    }
+
+    /// Evaluates every check and reports results to observer
+    fn evaluate_checks(
+        observer: &mut dyn PlaybookObserver,
+        stage: CheckStage,
+        checks: &[Box<dyn Check>],
+    ) -> Vec<CheckResult> {
+        observer.on_checks_start(stage);
+        let mut results = vec![];
+        for (i, next_check) in checks.iter().enumerate() {
+            let (yes, reason) = next_check.yes_explained();
+            observer.on_check_result(stage, i, next_check.name(), yes, reason.as_deref());
+            results.push(CheckResult::new(next_check.name(), yes).with_reason(reason));
+        }
+        results
+    }
+
+    /// Evaluates checks which all must be *yes*
+    fn require_checks(
+        observer: &mut dyn PlaybookObserver,
+        stage: CheckStage,
+        checks: &[Box<dyn Check>],
+        results: &mut Vec<CheckResult>,
+    ) -> Result<(), ()> {
+        if checks.is_empty() {
+            return Ok(());
+        }
+        *results = Self::evaluate_checks(observer, stage, checks);
+        let ok = results.iter().all(|c| c.yes);
+        observer.on_checks_finished(stage, ok);
+        if ok {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn apply_instruction<'a>(
+        &self,
+        observer: &mut dyn PlaybookObserver,
+        index: usize,
+        instruction: &'a Instruction,
+        report: &mut InstructionReport,
+        applied: &mut Vec<Applied<'a>>,
+        path: &[usize],
+    ) -> Result<(), ()> {
+        if !instruction.conditions.is_empty() {
+            let conditions =
+                Self::evaluate_checks(observer, CheckStage::Condition, &instruction.conditions);
+            let all_yes = conditions.iter().all(|c| c.yes);
+            observer.on_checks_finished(CheckStage::Condition, all_yes);
+            if !all_yes {
+                report.skipped = Some(SkipReason::ConditionNotMet);
+                return Ok(());
+            }
+        }
+        // checks before action
+        if !instruction.confirm_checks.is_empty() {
+            report.confirm_before = Self::evaluate_checks(
+                observer,
+                CheckStage::ConfirmBefore,
+                &instruction.confirm_checks,
+            );
+            let all_confirm_yes = report.confirm_before.iter().all(|c| c.yes);
+            let all_confirm_no = report.confirm_before.iter().all(|c| !c.yes);
+            observer
+                .on_checks_finished(CheckStage::ConfirmBefore, all_confirm_yes || all_confirm_no);
+            if !all_confirm_yes && !all_confirm_no {
+                return Err(());
+            }
+            if all_confirm_yes {
+                report.already_applied = true;
+                return Ok(());
+            }
+        }
+        Self::require_checks(
+            observer,
+            CheckStage::Env,
+            &instruction.env_checks,
+            &mut report.env,
+        )?;
+        let name = instruction.name();
+        let action = match &instruction.step {
+            Step::Action(action) => action,
+            Step::Group { instructions, .. } => {
+                let applied_before = applied.len();
+                observer.on_group_start(index, name);
+                let result = self.apply_instructions(
+                    observer,
+                    instructions,
+                    &mut report.instructions,
+                    applied,
+                    path,
+                    |_, _| None,
+                );
+                observer.on_group_finished(index, name, result.is_ok());
+                report.ran = report.instructions.iter().any(|i| i.ran);
+                if report.ran && instruction.on_fail.is_some() {
+                    // `on_fail` of group reverts whole group
+                    applied.truncate(applied_before);
+                    applied.push(Applied {
+                        path: path.to_vec(),
+                        instruction,
+                        undo: None,
+                    });
+                }
+                result?;
+                // checks after group
+                return Self::require_checks(
+                    observer,
+                    CheckStage::ConfirmAfter,
+                    &instruction.confirm_checks,
+                    &mut report.confirm_after,
+                );
+            }
+        };
+        applied.push(Applied {
+            path: path.to_vec(),
+            instruction,
+            undo: if self.rollback && instruction.on_fail.is_none() {
+                action.undo()
+            } else {
+                None
+            },
+        });
+        loop {
+            observer.on_action_start(index, name);
+            report.ran = true;
+            report.attempts += 1;
+            let action_started = Instant::now();
+            let run = || {
+                if instruction.backup.unwrap_or(self.backup) {
+                    match self.backup_run_dir() {
+                        Ok(run_dir) => backup::with_backup_dir(run_dir, || action.run_explained()),
+                        Err(reason) => (ActionResult::Fail, Some(reason)),
+                    }
+                } else {
+                    action.run_explained()
+                }
+            };
+            let external_output = instruction.external_output.unwrap_or(self.external_output);
+            let ((result, reason), output) = match external_output {
+                ExternalOutput::Hide => (run(), vec![]),
+                ExternalOutput::Live => {
+                    observer.on_live_output(index, name);
+                    (process::with_live_output(run), vec![])
+                }
+                ExternalOutput::OnFailure | ExternalOutput::Report => process::capture_output(run),
+            };
+            observer.on_action_finished(
+                index,
+                name,
+                result,
+                reason.as_deref(),
+                action_started.elapsed(),
+            );
+            report.reason = reason;
+            match external_output {
+                ExternalOutput::OnFailure if !result.ok() => {
+                    observer.on_external_output(index, name, &output);
+                }
+                ExternalOutput::Report => report.output.extend(output),
+                _ => {}
+            }
+            let result = if result.ok() {
+                // checks after action
+                Self::require_checks(
+                    observer,
+                    CheckStage::ConfirmAfter,
+                    &instruction.confirm_checks,
+                    &mut report.confirm_after,
+                )
+            } else {
+                Err(())
+            };
+            if result.is_ok() || report.attempts >= instruction.attempts {
+                return result;
+            }
+            let delay = instruction.backoff.delay(report.attempts);
+            observer.on_retry(index, report.attempts + 1, instruction.attempts, delay);
+            std::thread::sleep(delay);
+            if !instruction.confirm_checks.is_empty() {
+                // failed attempt could still apply changes
+                let confirm = Self::evaluate_checks(
+                    observer,
+                    CheckStage::ConfirmRetry,
+                    &instruction.confirm_checks,
+                );
+                let all_confirm_yes = confirm.iter().all(|c| c.yes);
+                observer.on_checks_finished(CheckStage::ConfirmRetry, all_confirm_yes);
+                if all_confirm_yes {
+                    report.confirm_after = confirm;
+                    return Ok(());
+                }
+            }
         }
     }
 
+    /// Applies instructions in order until first failure, `skip` selects
+    /// instructions which are skipped
+    fn apply_instructions<'a, Skip>(
+        &self,
+        observer: &mut dyn PlaybookObserver,
+        instructions: &'a [Instruction],
+        reports: &mut [InstructionReport],
+        applied: &mut Vec<Applied<'a>>,
+        path: &[usize],
+        skip: Skip,
+    ) -> Result<(), ()>
+    where
+        Skip: Fn(usize, &Instruction) -> Option<SkipReason>,
+    {
+        for (i, (instruction, report)) in instructions.iter().zip(reports).enumerate() {
+            if let Some(reason) = skip(i, instruction) {
+                report.skipped = Some(reason);
+                observer.on_instruction_skipped(i, instruction.name(), reason);
+                continue;
+            }
+            let path: Vec<usize> = path.iter().copied().chain([i]).collect();
+            observer.on_instruction_start(i, instruction.name());
+            let instruction_started = Instant::now();
+            let result = self.apply_instruction(observer, i, instruction, report, applied, &path);
+            report.duration = instruction_started.elapsed();
+            report.result = Some(result.into());
+            observer.on_instruction_finished(i, report);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Applies instructions of playbook concurrently respecting their
+    /// dependencies, events of each instruction are passed to observer when it
+    /// is finished. No new instructions are started after first failure
+    fn apply_parallel<'a, Skip>(
+        &'a self,
+        observer: &mut dyn PlaybookObserver,
+        reports: &mut [InstructionReport],
+        applied: &mut Vec<Applied<'a>>,
+        skip: Skip,
+    ) -> Result<(), ()>
+    where
+        Skip: Fn(usize, &Instruction) -> Option<SkipReason>,
+    {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum State {
+            Pending,
+            Running,
+            Done(bool),
+        }
+        let instructions = &self.instructions;
+        let dependencies = dependencies(instructions);
+        if let Some(cycle) = find_cycle(&dependencies) {
+            // nothing is started, instructions forming cycle are failed
+            let reason = describe_cycle(instructions, &cycle);
+            for i in cycle {
+                let name = instructions[i].name();
+                let report = &mut reports[i];
+                report.result = Some(ActionResult::Fail);
+                report.reason = Some(reason.clone());
+                observer.on_instruction_start(i, name);
+                observer.on_action_start(i, name);
+                observer.on_action_finished(
+                    i,
+                    name,
+                    ActionResult::Fail,
+                    Some(&reason),
+                    Duration::ZERO,
+                );
+                observer.on_instruction_finished(i, report);
+            }
+            return Err(());
+        }
+        let mut states = vec![State::Pending; instructions.len()];
+        for (i, (instruction, report)) in instructions.iter().zip(reports.iter_mut()).enumerate() {
+            if let Some(reason) = skip(i, instruction) {
+                report.skipped = Some(reason);
+                observer.on_instruction_skipped(i, instruction.name(), reason);
+                // skipped dependency is not blocking
+                states[i] = State::Done(true);
+            }
+        }
+        let deadline = process::deadline();
+        let mut failed = false;
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let mut running = 0;
+            loop {
+                for (i, instruction) in instructions.iter().enumerate() {
+                    let ready = dependencies[i]
+                        .iter()
+                        .all(|j| states[*j] == State::Done(true));
+                    if failed || running >= self.workers || states[i] != State::Pending || !ready {
+                        continue;
+                    }
+                    states[i] = State::Running;
+                    running += 1;
+                    let sender = sender.clone();
+                    let run_facts = facts::with_facts(|f| f.clone());
+                    scope.spawn(move || {
+                        let mut events = EventBuffer::default();
+                        let mut report = instruction.report();
+                        let mut applied = vec![];
+                        let mut apply = || {
+                            events.on_instruction_start(i, instruction.name());
+                            let instruction_started = Instant::now();
+                            let result = self.apply_instruction(
+                                &mut events,
+                                i,
+                                instruction,
+                                &mut report,
+                                &mut applied,
+                                &[i],
+                            );
+                            report.duration = instruction_started.elapsed();
+                            report.result = Some(result.into());
+                            events.on_instruction_finished(i, &report);
+                            result
+                        };
+                        let (result, run_facts) = facts::scope_with(run_facts, || match deadline {
+                            Some(deadline) => process::with_deadline(deadline, apply),
+                            None => apply(),
+                        });
+                        // receiver lives until all workers are finished
+                        let _ = sender.send((i, result, report, events, applied, run_facts));
+                    });
+                }
+                if running == 0 {
+                    break;
+                }
+                let Ok((i, result, report, events, worker_applied, run_facts)) = receiver.recv()
+                else {
+                    break;
+                };
+                running -= 1;
+                events.replay(observer);
+                reports[i] = report;
+                applied.extend(worker_applied);
+                facts::merge(run_facts);
+                states[i] = State::Done(result.is_ok());
+                failed = failed || result.is_err();
+            }
+        });
+        // instructions with failed dependencies are not started
+        if failed || states.contains(&State::Pending) {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs handlers notified by instructions which were run
+    fn run_handlers(
+        &self,
+        observer: &mut dyn PlaybookObserver,
+        instructions: &[InstructionReport],
+        handlers: &mut [HandlerReport],
+    ) -> Result<(), ()> {
+        fn notified<'a>(
+            instructions: &'a [Instruction],
+            reports: &[InstructionReport],
+            out: &mut Vec<&'a str>,
+        ) {
+            for (instruction, report) in instructions.iter().zip(reports) {
+                if !report.ran {
+                    continue;
+                }
+                out.extend(instruction.notify.iter().map(|n| n.as_str()));
+                if let Step::Group { instructions, .. } = &instruction.step {
+                    notified(instructions, &report.instructions, out);
+                }
+            }
+        }
+        let mut notified_handlers = vec![];
+        notified(&self.instructions, instructions, &mut notified_handlers);
+        let mut started = false;
+        let mut ok = true;
+        for (i, ((name, action), report)) in self.handlers.iter().zip(handlers).enumerate() {
+            if !notified_handlers.contains(&name.as_str()) {
+                continue;
+            }
+            if !started {
+                observer.on_handlers_start();
+                started = true;
+            }
+            observer.on_handler_start(i, name);
+            let (result, reason) = action.run_explained();
+            ok = result.ok() && ok;
+            report.result = Some(result);
+            observer.on_handler_finished(i, name, result, reason.as_deref());
+            report.reason = reason;
+        }
+        if started {
+            observer.on_handlers_finished(ok);
+        }
+        if ok {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Reverts instructions which were run, in reverse order
+    fn roll_back(
+        &self,
+        observer: &mut dyn PlaybookObserver,
+        report: &mut ApplyReport,
+        applied: &[Applied],
+    ) {
+        let steps: Vec<(&Applied, &dyn Action)> = applied
+            .iter()
+            .filter_map(|a| {
+                a.instruction
+                    .on_fail
+                    .as_deref()
+                    .or(a.undo.as_deref())
+                    .map(|rollback| (a, rollback))
+            })
+            .rev()
+            .collect();
+        if steps.is_empty() {
+            return;
+        }
+        observer.on_rollback_start();
+        let mut ok = true;
+        for (applied, rollback) in steps {
+            let (&index, parents) = applied.path.split_last().expect("path is not empty");
+            let name = applied.instruction.name();
+            observer.on_rollback_step_start(index, name);
+            let (result, reason) = rollback.run_explained();
+            ok = result.ok() && ok;
+            let mut reports = &mut report.instructions;
+            for i in parents {
+                reports = &mut reports[*i].instructions;
+            }
+            observer.on_rollback_step_finished(index, name, result, reason.as_deref());
+            reports[index].rolled_back = Some(result);
+            reports[index].rollback_reason = reason;
+        }
+        observer.on_rollback_finished(ok);
+    }
+
     // todo: test cases for each `if`
-    pub fn apply(&self) -> ActionResult {
-        let mut story = StoryFormatter::new();
-        story.playbook_header(self.name);
-        let playbook_result: ActionResult = story.section("Playbook", |story| {
+    /// Applies playbook printing its story, returns report with results of
+    /// every check and action
+    pub fn apply(&self) -> ApplyReport {
+        self.apply_with(&mut StoryObserver::new(self.output))
+    }
+
+    /// Applies playbook reporting progress only to provided observer (use
+    /// `Vec<Box<dyn PlaybookObserver>>` for multiple observers, and
+    /// [StoryObserver] for usual output). Number of failed instruction is
+    /// saved, so run can be resumed later with [Start::Resume] (not saved for
+    /// [Start::Only] and if rollback is enabled). Facts (see
+    /// [facts](crate::facts)) stored during run are dropped after it
+    pub fn apply_with(&self, observer: &mut dyn PlaybookObserver) -> ApplyReport {
+        facts::scope(|| self.apply_observed(observer))
+    }
+
+    /// Indexes of instructions selected with [Playbook::with_start]
+    fn selected(&self) -> Range<usize> {
+        match self.start {
+            Start::First => 0..self.instructions.len(),
+            Start::Resume => self.saved_progress().unwrap_or(0)..self.instructions.len(),
+            Start::From(i) => i..self.instructions.len(),
+            Start::Only(i) => i..(i + 1),
+        }
+    }
+
+    fn apply_observed(&self, observer: &mut dyn PlaybookObserver) -> ApplyReport {
+        let started = Instant::now();
+        // each run saves backups into its own directory
+        *self.backup_run.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        let selected = self.selected();
+        let mut report = ApplyReport {
+            name: self.name.to_owned(),
+            result: ActionResult::Fail,
+            env: vec![],
+            instructions: self.instructions.iter().map(Instruction::report).collect(),
+            handlers: self
+                .handlers
+                .iter()
+                .map(|(name, _)| HandlerReport {
+                    name: name.clone(),
+                    result: None,
+                    reason: None,
+                })
+                .collect(),
+            duration: Duration::ZERO,
+        };
+        let mut applied = vec![];
+        observer.on_playbook_start(self.name);
+        let mut apply_playbook = || {
+            Self::require_checks(
+                observer,
+                CheckStage::PlaybookEnv,
+                &self.env_checks,
+                &mut report.env,
+            )?;
+            let skip = |i, instruction: &Instruction| {
+                if !selected.contains(&i) {
+                    Some(SkipReason::Resume)
+                } else if !self.filter.matches(&instruction.tags) {
+                    Some(SkipReason::Filter)
+                } else {
+                    None
+                }
+            };
+            if self.workers > 1 {
+                self.apply_parallel(observer, &mut report.instructions, &mut applied, skip)?;
+            } else {
+                self.apply_instructions(
+                    observer,
+                    &self.instructions,
+                    &mut report.instructions,
+                    &mut applied,
+                    &[],
+                    skip,
+                )?;
+            }
+            self.run_handlers(observer, &report.instructions, &mut report.handlers)
+        };
+        report.result = apply_playbook().into();
+        if !report.ok() {
+            self.roll_back(observer, &mut report, &applied);
+        }
+        if !matches!(self.start, Start::Only(_)) {
+            let failed = report
+                .instructions
+                .iter()
+                .position(|i| i.result == Some(ActionResult::Fail));
+            match failed {
+                Some(i) if !self.rollback => self.save_progress(i),
+                // playbook environment failed, nothing to change
+                None if !report.ok() => {}
+                _ => self.clear_progress(),
+            }
+        }
+        report.duration = started.elapsed();
+        observer.on_playbook_finished(&report);
+        report
+    }
+
+    /// Checks instructions and prints results, returns `false` if some of
+    /// instructions can't be applied
+    fn check_instructions(
+        &self,
+        story: &mut StoryFormatter,
+        instructions: &[Instruction],
+        top_level: bool,
+    ) -> (bool, Vec<InstructionCheck>) {
+        let mut all_ok = true;
+        let mut checks = vec![];
+        // selection applies only to instructions of playbook, not of groups
+        let selected = top_level.then(|| self.selected());
+        for (i, instruction) in instructions.iter().enumerate() {
+            let action_name = format!("{}.{}", i + 1, instruction.name());
+            let mut status = InstructionStatus::WouldApply;
+            let mut reason = None;
+            let mut nested = vec![];
+            let instruction_result = story.section(action_name, |story| {
+                if selected.as_ref().is_some_and(|s| !s.contains(&i)) {
+                    status = InstructionStatus::SkippedByResume;
+                } else if top_level && !self.filter.matches(&instruction.tags) {
+                    status = InstructionStatus::SkippedByFilter;
+                }
+                if status == InstructionStatus::WouldApply && !instruction.conditions.is_empty() {
+                    let conditions: Vec<(_, _, _)> = instruction
+                        .conditions
+                        .iter()
+                        .map(|c| {
+                            let (yes, reason) = c.yes_explained();
+                            (c.name(), yes, reason)
+                        })
+                        .collect();
+                    let _ = story.checklist("Condition", |story| {
+                        Self::print_check_results(story, &conditions);
+                        if !conditions.iter().all(|(_, yes, _)| *yes) {
+                            status = InstructionStatus::ConditionNotMet;
+                            story.checklist_note(SkipReason::ConditionNotMet.describe());
+                        }
+                        Ok(())
+                    });
+                }
+                if status == InstructionStatus::WouldApply && !instruction.confirm_checks.is_empty()
+                {
+                    let confirm_checks: Vec<(_, _, _)> = instruction
+                        .confirm_checks
+                        .iter()
+                        .map(|c| {
+                            let (yes, reason) = c.yes_explained();
+                            (c.name(), yes, reason)
+                        })
+                        .collect();
+                    let all_confirm_yes = confirm_checks.iter().all(|(_, yes, _)| *yes);
+                    let all_confirm_no = confirm_checks.iter().all(|(_, yes, _)| !*yes);
+                    reason =
+                        confirm_checks
+                            .iter()
+                            .find_map(|(_, yes, reason)| if *yes { None } else { reason.clone() });
+                    let _ = story.checklist("Confirmation", |story| {
+                        Self::print_check_results(story, &confirm_checks);
+                        if all_confirm_yes {
+                            status = InstructionStatus::AlreadyApplied;
+                            Ok(())
+                        } else if all_confirm_no {
+                            Ok(())
+                        } else {
+                            status = InstructionStatus::ConfirmationMixed;
+                            story.checklist_note(
+                                "confirmation checks should be *all yes* or *all no*",
+                            );
+                            Err(())
+                        }
+                    });
+                }
+                if status == InstructionStatus::WouldApply
+                    && !instruction.env_checks.is_empty()
+                    && story
+                        .checklist("Environment", |story| {
+                            Self::check_checks(story, &instruction.env_checks)
+                        })
+                        .is_err()
+                {
+                    status = InstructionStatus::BlockedByEnv;
+                }
+                if let (InstructionStatus::WouldApply, Step::Group { instructions, .. }) =
+                    (status, &instruction.step)
+                {
+                    let _ = story.section("Actions", |story| {
+                        let (ok, checks) = self.check_instructions(story, instructions, false);
+                        nested = checks;
+                        if ok {
+                            Ok(())
+                        } else {
+                            Err(())
+                        }
+                    });
+                    status = InstructionStatus::group(&nested);
+                }
+                story.section_note(status.describe());
+                match status {
+                    InstructionStatus::AlreadyApplied
+                    | InstructionStatus::WouldApply
+                    | InstructionStatus::ConditionNotMet
+                    | InstructionStatus::SkippedByFilter
+                    | InstructionStatus::SkippedByResume => Ok(()),
+                    InstructionStatus::BlockedByEnv | InstructionStatus::ConfirmationMixed => {
+                        Err(())
+                    }
+                }
+            });
+            all_ok = instruction_result.is_ok() && all_ok;
+            if status != InstructionStatus::WouldApply {
+                reason = None;
+            }
+            checks.push(InstructionCheck {
+                name: instruction.name().to_owned(),
+                status,
+                reason,
+                instructions: nested,
+            });
+        }
+        (all_ok, checks)
+    }
+
+    /// Evaluates environment and confirmation checks of every instruction
+    /// without running any action, reports what would change if playbook is
+    /// applied. Actions are not run, so checks can't use facts stored by them.
+    /// Instructions not selected with [Playbook::with_start] are reported as
+    /// skipped, same as with [Playbook::apply]
+    pub fn check(&self) -> CheckReport {
+        facts::scope(|| self.check_story())
+    }
+
+    fn check_story(&self) -> CheckReport {
+        let mut story = StoryFormatter::new(self.output);
+        story.playbook_check_header(self.name);
+        let mut report = CheckReport {
+            name: self.name.to_owned(),
+            env_ok: true,
+            instructions: vec![],
+        };
+        let _ = story.section("Playbook", |story| {
             if !self.env_checks.is_empty() {
-                story.checklist("Environment", |story| {
-                    Self::check_checks(story, &self.env_checks, true)
-                })?;
-            }
-            if !self.instructions.is_empty() {
-                story.section("Actions", |story| {
-                    for (i, instruction) in self.instructions.iter().enumerate() {
-                        let action_name = format!("{}.{}", i + 1, instruction.action.name());
-                        story.section(action_name, |story| {
-                            // checks before action
-                            let mut action_already_applied = false;
-                            story.section("pre", |story| {
-                                if !instruction.confirm_checks.is_empty() {
-                                    let confirm_checks: Vec<(_, _)> = instruction
-                                        .confirm_checks
-                                        .iter()
-                                        .map(|c| (c.name(), c.yes()))
-                                        .collect();
-                                    let all_confirm_yes =
-                                        confirm_checks.iter().all(|(_, yes)| *yes);
-                                    let all_confirm_no =
-                                        confirm_checks.iter().all(|(_, yes)| !*yes);
-                                    story.checklist("Confirmation", |story| {
-                                        if !all_confirm_yes && !all_confirm_no {
-                                            Self::print_check_results(story, &confirm_checks);
-                                            story.checklist_note("confirmation checks should be *all yes* or *all no*");
-                                            return Err(());
-                                        }
-                                        if all_confirm_yes {
-                                            Self::print_check_results(story, &confirm_checks);
-                                            story
-                                                .checklist_note("action already applied, skipping");
-                                            action_already_applied = true;
-                                        } else if all_confirm_no {
-                                            story.checklist_title_note("checking all confirmations is *no*");
-                                            Self::print_check_results(story, &confirm_checks);
-                                        } else {
-                                            unreachable!()
-                                        }
-                                        Ok(()) // -- end .Confirmation
-                                    })?;
-                                }
-                                if action_already_applied {
-                                    return Ok(());
-                                }
-                                if !instruction.env_checks.is_empty() {
-                                    story.checklist("Environment", |story| {
-                                        Self::check_checks(story, &instruction.env_checks, true)
-                                    })?;
-                                }
-                                Ok(()) // -- end .pre
-                            })?;
-                            if action_already_applied {
-                                return Ok(());
-                            }
-                            story.process("apply", |_| {
-                                match instruction.action.run() {
-                                    ActionResult::Ok => Ok(()),
-                                    ActionResult::Fail => Err(()),
-                                }
-                            })?;
-                            // checks after action
-                            story.section("post", |story| {
-                                if !instruction.confirm_checks.is_empty() {
-                                    story.checklist("Confirmation", |story|{
-                                        Self::check_checks(story, &instruction.confirm_checks, true)
-                                    })
-                                } else {
-                                    Ok(())
-                                }
-                            })?;
-                            Ok(()) // -- end .Instruction
-                        })?;
+                report.env_ok = story
+                    .checklist("Environment", |story| {
+                        Self::check_checks(story, &self.env_checks)
+                    })
+                    .is_ok();
+                if !report.env_ok {
+                    return Err(());
+                }
+            }
+            if self.instructions.is_empty() {
+                return Ok(());
+            }
+            story.section("Actions", |story| {
+                let (all_ok, instructions) =
+                    self.check_instructions(story, &self.instructions, true);
+                report.instructions = instructions;
+                if all_ok {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            })
+        });
+        story.playbook_check_result(self.name, report.drift());
+        report
+    }
+
+    /// Finds issues which can be found without applying playbook, like
+    /// duplicated names, instructions without confirmation checks or
+    /// notifications of unknown handlers
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = vec![];
+        let mut error = |message: String| issues.push(Issue::new(Severity::Error, None, message));
+        if self.name == UNNAMED {
+            error("playbook has no name".to_owned());
+        } else if !is_file_name(self.name) {
+            error(format!(
+                "playbook name `{}` can't be used as file name (it is used to save progress)",
+                self.name
+            ));
+        }
+        for (i, (name, _)) in self.handlers.iter().enumerate() {
+            if self.handlers[..i].iter().any(|(other, _)| other == name) {
+                error(format!("handler `{name}` is registered multiple times"));
+            }
+        }
+        if self.description == UNDESCRIBED {
+            issues.push(Issue::new(
+                Severity::Warning,
+                None,
+                "playbook has no description",
+            ));
+        }
+        if self.instructions.is_empty() {
+            issues.push(Issue::new(
+                Severity::Warning,
+                None,
+                "playbook has no instructions",
+            ));
+        }
+        if let Some(cycle) = find_cycle(&dependencies(&self.instructions)) {
+            issues.push(Issue::new(
+                Severity::Error,
+                None,
+                describe_cycle(&self.instructions, &cycle),
+            ));
+        }
+        let mut notified = vec![];
+        self.validate_instructions(&self.instructions, "", &mut notified, &mut issues);
+        for (name, _) in &self.handlers {
+            if !notified.contains(&name.as_str()) {
+                issues.push(Issue::new(
+                    Severity::Warning,
+                    None,
+                    format!("handler `{name}` is never notified"),
+                ));
+            }
+        }
+        issues
+    }
+
+    fn validate_instructions<'a>(
+        &self,
+        instructions: &'a [Instruction],
+        prefix: &str,
+        notified: &mut Vec<&'a str>,
+        issues: &mut Vec<Issue>,
+    ) {
+        let top_level = prefix.is_empty();
+        for (i, instruction) in instructions.iter().enumerate() {
+            let name = instruction.name();
+            let number = format!("{prefix}{}", i + 1);
+            let location = Some(format!("{number}.{name}"));
+            let mut issue = |severity, message: String| {
+                issues.push(Issue::new(severity, location.clone(), message));
+            };
+            if instructions[..i].iter().any(|other| other.name() == name) {
+                issue(
+                    Severity::Warning,
+                    format!("name `{name}` is used by multiple instructions, only first one can be found by name"),
+                );
+            }
+            for dependency in &instruction.depends_on {
+                if !top_level {
+                    issue(
+                        Severity::Warning,
+                        format!("dependency on `{dependency}` is ignored inside of group"),
+                    );
+                } else if dependency == name {
+                    issue(Severity::Error, "instruction depends on itself".to_owned());
+                } else if !instructions.iter().any(|other| other.name() == dependency) {
+                    issue(
+                        Severity::Error,
+                        format!("depends on unknown instruction `{dependency}`"),
+                    );
+                }
+            }
+            for handler in &instruction.notify {
+                notified.push(handler);
+                if !self.handlers.iter().any(|(other, _)| other == handler) {
+                    issue(
+                        Severity::Error,
+                        format!("notifies unknown handler `{handler}`"),
+                    );
+                }
+            }
+            for check in &instruction.confirm_checks {
+                match check.kind() {
+                    "AlwaysYes" => issue(
+                        Severity::Warning,
+                        format!(
+                            "confirmation `{}` is always yes, action is never run",
+                            check.name()
+                        ),
+                    ),
+                    "AlwaysNo" => issue(
+                        Severity::Error,
+                        format!(
+                            "confirmation `{}` is always no, instruction always fails",
+                            check.name()
+                        ),
+                    ),
+                    _ => {}
+                }
+            }
+            match &instruction.step {
+                Step::Action(action) => {
+                    if instruction.confirm_checks.is_empty() {
+                        issue(
+                            Severity::Warning,
+                            "instruction has no confirmation checks, its action is run each time playbook is applied".to_owned(),
+                        );
+                    }
+                    for check in &instruction.confirm_checks {
+                        if contradicts(action.kind(), check.kind()) {
+                            issue(
+                                Severity::Warning,
+                                format!(
+                                    "confirmation `{}` ({}) contradicts action `{}` ({})",
+                                    check.name(),
+                                    check.kind(),
+                                    action.name(),
+                                    action.kind()
+                                ),
+                            );
+                        }
                     }
-                    Ok(()) // -- end .Actions
-                })?;
+                }
+                Step::Group { instructions, .. } => {
+                    if instructions.is_empty() {
+                        issue(Severity::Warning, "group has no instructions".to_owned());
+                    }
+                    self.validate_instructions(
+                        instructions,
+                        &format!("{number}."),
+                        notified,
+                        issues,
+                    );
+                }
             }
-            Ok(()) // -- end .Playbook
-        }).into();
-        StoryFormatter::playbook_result(self.name, playbook_result.ok());
-        playbook_result
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        actions::always_ok,
-        checks::{always_no, always_yes},
+        actions::{action, always_fail, always_ok, command, create_dir, delete_file, write_file},
+        checks::{always_no, always_yes, check, is_file, path_is_missing, stdout_contains_once},
+        interfaces::ExecContext,
+        report::{CheckResult, InstructionStatus},
+    };
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
     };
-    use std::cell::RefCell;
 
     use super::*;
 
     /// Check which changes result each time it checked
     struct AlwaysFlips {
-        result: RefCell<bool>,
+        result: AtomicBool,
     }
 
     impl AlwaysFlips {
@@ -237,7 +1585,7 @@ mod tests {
 
         pub fn new(next_result: bool) -> Self {
             Self {
-                result: RefCell::new(next_result),
+                result: AtomicBool::new(next_result),
             }
         }
     }
@@ -247,11 +1595,8 @@ mod tests {
             Self::NAME
         }
 
-        fn yes(&self) -> bool {
-            let mut result = self.result.try_borrow_mut().unwrap();
-            let next_result = *result;
-            *result = !*result;
-            next_result
+        fn yes_in(&self, _ctx: &ExecContext) -> (bool, Option<String>) {
+            (self.result.fetch_xor(true, Ordering::SeqCst), None)
         }
 
         fn into_check(self) -> Box<dyn Check> {
@@ -329,4 +1674,955 @@ mod tests {
         .apply()
         .ok());
     }
+
+    #[test]
+    fn test_playbook_check() {
+        let must_not_run = || -> Box<dyn Action> {
+            ("must not run", || -> ActionResult {
+                panic!("action is run")
+            })
+                .into_action()
+        };
+        let report =
+            Playbook::new("env-fail", "", [always_no()], [instruction(must_not_run())]).check();
+        assert!(!report.env_ok);
+        assert!(report.instructions.is_empty());
+        assert!(report.drift());
+        let report = Playbook::new(
+            "statuses",
+            "",
+            [always_yes()],
+            [
+                instruction(must_not_run()).confirm(always_yes()),
+                instruction(must_not_run()).confirm(always_no()),
+                instruction(must_not_run()),
+                instruction(must_not_run())
+                    .with_env(always_no())
+                    .confirm(always_no()),
+                instruction(must_not_run()).confirm([always_yes(), always_no()]),
+                instruction(must_not_run())
+                    .with_env(always_no())
+                    .confirm(always_yes()),
+                instruction(must_not_run())
+                    .when(always_no())
+                    .with_env(always_no()),
+            ],
+        )
+        .check();
+        assert!(report.env_ok);
+        let statuses: Vec<_> = report.instructions.iter().map(|i| i.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                InstructionStatus::AlreadyApplied,
+                InstructionStatus::WouldApply,
+                InstructionStatus::WouldApply,
+                InstructionStatus::BlockedByEnv,
+                InstructionStatus::ConfirmationMixed,
+                InstructionStatus::AlreadyApplied,
+                InstructionStatus::ConditionNotMet,
+            ]
+        );
+        assert_eq!(report.count(InstructionStatus::WouldApply), 2);
+        let report = Playbook::new(
+            "reason",
+            "",
+            [],
+            [instruction(must_not_run()).confirm(stdout_contains_once(["echo", "aa"], "a"))],
+        )
+        .check();
+        assert_eq!(
+            report.instructions[0].reason.as_deref(),
+            Some("pattern matched 2 times")
+        );
+        assert!(report.drift());
+        let report = Playbook::new(
+            "no-drift",
+            "",
+            [],
+            [
+                instruction(must_not_run()).confirm(always_yes()),
+                instruction(must_not_run()).when([always_yes(), always_no()]),
+            ],
+        )
+        .check();
+        assert!(!report.drift());
+        assert!(!Playbook::new("empty", "", [], []).check().drift());
+        // instructions not selected to be applied are skipped
+        let selection = |start: Start| {
+            Playbook::new(
+                "selection",
+                "",
+                [],
+                [
+                    instruction(must_not_run()).confirm(always_no()),
+                    instruction(must_not_run()).confirm(always_yes()),
+                    instruction(must_not_run()).confirm(always_no()),
+                ],
+            )
+            .with_start(start)
+            .check()
+        };
+        let report = selection(Start::Only(1));
+        let statuses: Vec<_> = report.instructions.iter().map(|i| i.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                InstructionStatus::SkippedByResume,
+                InstructionStatus::AlreadyApplied,
+                InstructionStatus::SkippedByResume,
+            ]
+        );
+        assert!(!report.drift());
+        assert!(selection(Start::From(2)).drift());
+    }
+
+    #[test]
+    fn test_apply_report() {
+        let report = Playbook::new(
+            "report",
+            "",
+            [always_yes()],
+            [
+                instruction(always_ok()).confirm(always_yes()),
+                instruction(always_ok())
+                    .with_env(always_yes())
+                    .confirm(flip(false)),
+                instruction(always_ok()).with_env(always_no()),
+                instruction(always_ok()),
+            ],
+        )
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.env, vec![CheckResult::new("AlwaysYes", true)]);
+        assert_eq!(report.instructions.len(), 4);
+        let [applied, ran, blocked, not_reached] = &report.instructions[..] else {
+            panic!("expecting 4 instructions");
+        };
+        assert!(applied.already_applied);
+        assert!(!applied.ran);
+        assert_eq!(applied.result, Some(ActionResult::Ok));
+        assert_eq!(
+            applied.confirm_before,
+            vec![CheckResult::new("AlwaysYes", true)]
+        );
+        assert!(applied.confirm_after.is_empty());
+        assert!(!ran.already_applied);
+        assert!(ran.ran);
+        assert_eq!(ran.result, Some(ActionResult::Ok));
+        assert_eq!(ran.env, vec![CheckResult::new("AlwaysYes", true)]);
+        assert_eq!(
+            ran.confirm_before,
+            vec![CheckResult::new("AlwaysFlips", false)]
+        );
+        assert_eq!(
+            ran.confirm_after,
+            vec![CheckResult::new("AlwaysFlips", true)]
+        );
+        assert!(!blocked.ran);
+        assert_eq!(blocked.env, vec![CheckResult::new("AlwaysNo", false)]);
+        assert_eq!(blocked.result, Some(ActionResult::Fail));
+        assert!(!not_reached.ran);
+        assert_eq!(not_reached.result, None);
+    }
+
+    #[test]
+    fn test_failure_reason() {
+        let report = Playbook::new(
+            "failure-reason",
+            "",
+            [],
+            [instruction(always_ok()).with_env(stdout_contains_once(["echo", "aa"], "a"))],
+        )
+        .apply();
+        assert_eq!(
+            report.instructions[0].env[0].reason.as_deref(),
+            Some("pattern matched 2 times")
+        );
+        let report = Playbook::new(
+            "failure-reason",
+            "",
+            [],
+            [instruction(command(["sh", "-c", "echo oops >&2; exit 1"]))],
+        )
+        .apply();
+        assert_eq!(
+            report.instructions[0].reason.as_deref(),
+            Some("process exited with error code 1, stderr:\noops")
+        );
+    }
+
+    #[test]
+    fn test_rollback() {
+        let file_path = "/tmp/pass-test-file-111222333-test_rollback";
+        let dir_path = "/tmp/pass-test-dir-111222333-test_rollback";
+        let report = Playbook::new(
+            "rollback",
+            "",
+            [],
+            [
+                instruction(write_file(file_path, "111")),
+                instruction(create_dir(dir_path)),
+                instruction(always_ok()).confirm(always_yes()),
+                instruction(always_fail()),
+                instruction(always_ok()),
+            ],
+        )
+        .with_rollback()
+        .apply();
+        assert!(!report.ok());
+        assert!(!PathBuf::from(file_path).exists());
+        assert!(!PathBuf::from(dir_path).exists());
+        let rolled_back: Vec<_> = report.instructions.iter().map(|i| i.rolled_back).collect();
+        assert_eq!(
+            rolled_back,
+            vec![
+                Some(ActionResult::Ok),
+                Some(ActionResult::Ok),
+                None,
+                None,
+                None
+            ]
+        );
+        // without automatic rollback only `on_fail` is used
+        let undone = Arc::new(AtomicUsize::new(0));
+        let undone_copy = undone.clone();
+        let report = Playbook::new(
+            "on-fail",
+            "",
+            [],
+            [
+                instruction(write_file(file_path, "111")),
+                instruction(always_ok()).on_fail(
+                    ("count undo", move || {
+                        undone_copy.fetch_add(1, Ordering::SeqCst);
+                        ActionResult::Ok
+                    })
+                        .into_action(),
+                ),
+                instruction(always_ok())
+                    .confirm(flip(false))
+                    .on_fail(command(["sh", "-c", "echo undo failed >&2; exit 1"])),
+                instruction(always_fail()),
+            ],
+        )
+        .apply();
+        assert!(!report.ok());
+        assert!(PathBuf::from(file_path).is_file());
+        std::fs::remove_file(file_path).unwrap();
+        assert_eq!(undone.load(Ordering::SeqCst), 1);
+        let rolled_back: Vec<_> = report.instructions.iter().map(|i| i.rolled_back).collect();
+        assert_eq!(
+            rolled_back,
+            vec![None, Some(ActionResult::Ok), Some(ActionResult::Fail), None]
+        );
+        assert_eq!(report.instructions[1].rollback_reason, None);
+        assert_eq!(
+            report.instructions[2].rollback_reason.as_deref(),
+            Some("process exited with error code 1, stderr:\nundo failed")
+        );
+        // nothing is reverted if playbook succeeded
+        let report = Playbook::new(
+            "no-rollback",
+            "",
+            [],
+            [instruction(always_ok()).on_fail(always_fail())],
+        )
+        .with_rollback()
+        .apply();
+        assert!(report.ok());
+        assert_eq!(report.instructions[0].rolled_back, None);
+    }
+
+    #[test]
+    fn test_resume() {
+        let progress_dir = "/tmp/pass-test-dir-111222333-test_resume";
+        let _ = std::fs::remove_dir_all(progress_dir);
+        let runs = Arc::new(Mutex::new(vec![]));
+        let playbook = |fail: bool| {
+            let action = |n: usize| {
+                let runs = runs.clone();
+                (format!("action-{n}"), move || {
+                    runs.lock().unwrap().push(n);
+                    if n == 2 && fail {
+                        ActionResult::Fail
+                    } else {
+                        ActionResult::Ok
+                    }
+                })
+                    .into_action()
+            };
+            Playbook::new(
+                "resume",
+                "",
+                [],
+                [
+                    instruction(action(0)),
+                    instruction(action(1)),
+                    instruction(action(2)),
+                    instruction(action(3)),
+                ],
+            )
+            .with_progress_dir(progress_dir)
+        };
+        assert_eq!(playbook(false).find_instruction("2"), Some(1));
+        assert_eq!(playbook(false).find_instruction("action-3"), Some(3));
+        assert_eq!(playbook(false).find_instruction("5"), None);
+        assert_eq!(playbook(false).find_instruction("action-5"), None);
+        assert!(!playbook(true).apply().ok());
+        assert_eq!(playbook(true).saved_progress(), Some(2));
+        let report = playbook(false).with_start(Start::Resume).apply();
+        assert!(report.ok());
+        let skipped: Vec<_> = report.instructions.iter().map(|i| i.skipped).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                Some(SkipReason::Resume),
+                Some(SkipReason::Resume),
+                None,
+                None
+            ]
+        );
+        assert_eq!(playbook(false).saved_progress(), None);
+        assert!(playbook(false).with_start(Start::From(3)).apply().ok());
+        assert!(playbook(false).with_start(Start::Only(1)).apply().ok());
+        assert_eq!(*runs.lock().unwrap(), vec![0, 1, 2, 2, 3, 3, 1]);
+        std::fs::remove_dir_all(progress_dir).unwrap();
+    }
+
+    #[test]
+    fn test_handlers() {
+        let runs = Arc::new(Mutex::new(vec![]));
+        let handler = |name: &'static str| {
+            let runs = runs.clone();
+            (name, move || {
+                runs.lock().unwrap().push(name);
+                ActionResult::Ok
+            })
+                .into_action()
+        };
+        let report = Playbook::new(
+            "handlers",
+            "",
+            [],
+            [
+                instruction(always_ok()).notify("reload"),
+                instruction(always_ok())
+                    .confirm(flip(false))
+                    .notify("reload")
+                    .notify("restart"),
+                instruction(always_ok())
+                    .confirm(always_yes())
+                    .notify("not-notified"),
+            ],
+        )
+        .with_handler("restart", handler("restart"))
+        .with_handler("not-notified", handler("not-notified"))
+        .with_handler("reload", handler("reload"))
+        .apply();
+        assert!(report.ok());
+        // handlers run once, in order of registration
+        assert_eq!(*runs.lock().unwrap(), vec!["restart", "reload"]);
+        let results: Vec<_> = report.handlers.iter().map(|h| h.result).collect();
+        assert_eq!(
+            results,
+            vec![Some(ActionResult::Ok), None, Some(ActionResult::Ok)]
+        );
+        // handlers are not run if playbook failed
+        let report = Playbook::new(
+            "handlers-fail",
+            "",
+            [],
+            [
+                instruction(always_ok()).notify("reload"),
+                instruction(always_fail()),
+            ],
+        )
+        .with_handler("reload", handler("reload"))
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.handlers[0].result, None);
+        let report = Playbook::new(
+            "handler-fails",
+            "",
+            [],
+            [instruction(always_ok()).notify("fail")],
+        )
+        .with_handler("fail", always_fail())
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.handlers[0].result, Some(ActionResult::Fail));
+    }
+
+    #[test]
+    fn test_when() {
+        let report = Playbook::new(
+            "when",
+            "",
+            [],
+            [
+                instruction(always_fail())
+                    .when(always_no())
+                    .with_env(always_no())
+                    .notify("handler"),
+                instruction(always_ok()).when([always_yes(), always_yes()]),
+            ],
+        )
+        .with_handler("handler", always_fail())
+        .apply();
+        assert!(report.ok());
+        let [skipped, applied] = &report.instructions[..] else {
+            panic!("expecting 2 instructions");
+        };
+        assert_eq!(skipped.skipped, Some(SkipReason::ConditionNotMet));
+        assert!(!skipped.ran);
+        assert!(skipped.env.is_empty());
+        assert_eq!(applied.skipped, None);
+        assert!(applied.ran);
+        assert_eq!(report.handlers[0].result, None);
+    }
+
+    #[test]
+    fn test_tags() {
+        let filter = |tags: &[&str], skip_tags: &[&str]| TagFilter {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            skip_tags: skip_tags.iter().map(|t| t.to_string()).collect(),
+        };
+        let playbook = || {
+            Playbook::new(
+                "tags",
+                "",
+                [],
+                [
+                    instruction(always_ok()).tag("firewall"),
+                    instruction(always_ok()).tag("tls").tag("nginx"),
+                    instruction(always_ok()),
+                ],
+            )
+        };
+        let skipped = |filter: TagFilter| -> Vec<_> {
+            playbook()
+                .with_filter(filter)
+                .apply()
+                .instructions
+                .iter()
+                .map(|i| i.skipped)
+                .collect()
+        };
+        assert_eq!(skipped(filter(&[], &[])), vec![None, None, None]);
+        assert_eq!(
+            skipped(filter(&["firewall", "nginx"], &[])),
+            vec![None, None, Some(SkipReason::Filter)]
+        );
+        assert_eq!(
+            skipped(filter(&[], &["tls"])),
+            vec![None, Some(SkipReason::Filter), None]
+        );
+        assert_eq!(
+            skipped(filter(&["tls"], &["nginx"])),
+            vec![
+                Some(SkipReason::Filter),
+                Some(SkipReason::Filter),
+                Some(SkipReason::Filter)
+            ]
+        );
+        let report = playbook().with_filter(filter(&["firewall"], &[])).check();
+        assert_eq!(report.count(InstructionStatus::SkippedByFilter), 2);
+    }
+
+    #[test]
+    fn test_groups() {
+        let file_path = "/tmp/pass-test-file-111222333-test_groups";
+        let harden = || {
+            Playbook::new(
+                "harden",
+                "",
+                [always_yes()],
+                [
+                    instruction(always_ok()).confirm(always_yes()),
+                    instruction(write_file(file_path, "111")),
+                ],
+            )
+        };
+        let report = Playbook::new(
+            "groups",
+            "",
+            [],
+            [
+                harden().into(),
+                group("blocked", always_no(), [instruction(always_ok())]),
+            ],
+        )
+        .with_rollback()
+        .apply();
+        assert!(!report.ok());
+        let [nested, blocked] = &report.instructions[..] else {
+            panic!("expecting 2 instructions");
+        };
+        assert_eq!(nested.name, "harden");
+        assert!(nested.ran);
+        assert_eq!(nested.env, vec![CheckResult::new("AlwaysYes", true)]);
+        assert_eq!(nested.instructions.len(), 2);
+        assert!(nested.instructions[0].already_applied);
+        assert!(nested.instructions[1].ran);
+        // nested instruction is reverted
+        assert_eq!(nested.instructions[1].rolled_back, Some(ActionResult::Ok));
+        assert!(!PathBuf::from(file_path).exists());
+        assert!(!blocked.ran);
+        assert_eq!(blocked.result, Some(ActionResult::Fail));
+        assert_eq!(blocked.instructions[0].result, None);
+        // failed instruction fails group, `on_fail` of group replaces
+        // reverting of its instructions
+        let report = Playbook::new(
+            "group-fail",
+            "",
+            [],
+            [
+                group("failing", [], [instruction(always_ok()).notify("handler")])
+                    .on_fail(always_ok()),
+                group(
+                    "fails",
+                    [],
+                    [
+                        instruction(write_file(file_path, "111")),
+                        instruction(always_fail()),
+                        instruction(always_ok()),
+                    ],
+                ),
+            ],
+        )
+        .with_rollback()
+        .with_handler("handler", always_ok())
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.instructions[0].rolled_back, Some(ActionResult::Ok));
+        let fails = &report.instructions[1];
+        assert_eq!(fails.result, Some(ActionResult::Fail));
+        assert_eq!(fails.instructions[0].rolled_back, Some(ActionResult::Ok));
+        assert_eq!(fails.instructions[2].result, None);
+        assert!(!PathBuf::from(file_path).exists());
+        assert_eq!(report.handlers[0].result, None);
+        // check looks into groups
+        let report = Playbook::new(
+            "group-check",
+            "",
+            [],
+            [
+                group(
+                    "applied",
+                    [],
+                    [instruction(always_ok()).confirm(always_yes())],
+                ),
+                group(
+                    "would-apply",
+                    [],
+                    [
+                        instruction(always_ok()).confirm(always_yes()),
+                        instruction(always_ok()),
+                    ],
+                ),
+                group(
+                    "blocked",
+                    [],
+                    [instruction(always_ok()).with_env(always_no())],
+                ),
+            ],
+        )
+        .check();
+        let statuses: Vec<_> = report.instructions.iter().map(|i| i.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                InstructionStatus::AlreadyApplied,
+                InstructionStatus::WouldApply,
+                InstructionStatus::BlockedByEnv,
+            ]
+        );
+        assert_eq!(report.instructions[1].instructions.len(), 2);
+    }
+
+    #[test]
+    fn test_facts() {
+        use crate::{
+            actions::{command_output_into, lazy},
+            checks::fact_is,
+        };
+        let playbook = Playbook::new(
+            "facts",
+            "",
+            [],
+            [
+                instruction(command_output_into("password", ["echo", "secret"]))
+                    .confirm(fact_is("password", "secret")),
+                instruction(lazy("write password", |facts| {
+                    write_file(
+                        "/tmp/pass-test-file-111222333-test_facts",
+                        facts.get("password").unwrap_or_default(),
+                    )
+                })),
+            ],
+        );
+        assert!(playbook.apply().ok());
+        assert_eq!(
+            std::fs::read_to_string("/tmp/pass-test-file-111222333-test_facts").unwrap(),
+            "secret"
+        );
+        std::fs::remove_file("/tmp/pass-test-file-111222333-test_facts").unwrap();
+        // facts are not kept between runs
+        assert_eq!(crate::facts::get_fact("password"), None);
+    }
+
+    #[test]
+    fn test_parallel() {
+        use crate::{
+            actions::{command_output_into, lazy},
+            checks::fact_is,
+        };
+        let events = Arc::new(Mutex::new(vec![]));
+        let sleep = |name: &'static str| {
+            let events = events.clone();
+            (name, move || {
+                events.lock().unwrap().push(format!("{name} start"));
+                std::thread::sleep(Duration::from_millis(300));
+                events.lock().unwrap().push(format!("{name} end"));
+                ActionResult::Ok
+            })
+                .into_action()
+        };
+        let started = Instant::now();
+        let report = Playbook::new(
+            "parallel",
+            "",
+            [],
+            [
+                instruction(sleep("a")),
+                instruction(sleep("b")),
+                instruction(sleep("c")).depends_on("a"),
+                instruction(command_output_into("ip", ["echo", "127.0.0.1"])),
+                instruction(lazy("uses fact", |facts| {
+                    if facts.get("ip") == Some("127.0.0.1") {
+                        always_ok()
+                    } else {
+                        always_fail()
+                    }
+                }))
+                .depends_on("CommandOutputInto"),
+            ],
+        )
+        .with_workers(3)
+        .apply();
+        assert!(report.ok());
+        assert!(started.elapsed() < Duration::from_millis(900));
+        let events = events.lock().unwrap();
+        let position = |event: &str| events.iter().position(|e| e == event).unwrap();
+        assert!(position("a end") < position("c start"));
+        assert!(position("b start") < position("a end"));
+        // facts of parallel run are merged into run facts
+        assert!(Playbook::new(
+            "parallel-facts",
+            "",
+            [],
+            [
+                instruction(command_output_into("ip", ["echo", "127.0.0.1"])),
+                instruction(always_ok()).depends_on("CommandOutputInto"),
+                instruction(always_ok())
+                    .confirm(flip(false))
+                    .with_env(fact_is("ip", "127.0.0.1"))
+                    .depends_on("AlwaysOk"),
+            ],
+        )
+        .with_workers(2)
+        .apply()
+        .ok());
+        // dependent instructions are not started after failure
+        let report = Playbook::new(
+            "parallel-fail",
+            "",
+            [],
+            [
+                instruction(always_fail()),
+                instruction(always_ok()).depends_on("AlwaysFail"),
+            ],
+        )
+        .with_workers(2)
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.instructions[0].result, Some(ActionResult::Fail));
+        assert_eq!(report.instructions[1].result, None);
+        // circular dependencies
+        let report = Playbook::new(
+            "parallel-cycle",
+            "",
+            [],
+            [
+                instruction(always_ok()).depends_on("AlwaysFail"),
+                instruction(always_fail()).depends_on("AlwaysOk"),
+            ],
+        )
+        .with_workers(2)
+        .apply();
+        assert!(!report.ok());
+        assert!(report.instructions.iter().all(|i| !i.ran));
+        assert_eq!(
+            report.instructions[0].reason.as_deref(),
+            Some("dependency cycle: AlwaysOk -> AlwaysFail -> AlwaysOk")
+        );
+        // skipped dependency is not blocking
+        let report = Playbook::new(
+            "parallel-skip",
+            "",
+            [],
+            [
+                instruction(always_fail()).when(always_no()),
+                instruction(always_ok()).depends_on("AlwaysFail"),
+            ],
+        )
+        .with_workers(2)
+        .apply();
+        assert!(report.ok());
+        assert_eq!(
+            report.instructions[0].skipped,
+            Some(SkipReason::ConditionNotMet)
+        );
+        assert!(report.instructions[1].ran);
+    }
+
+    #[test]
+    fn test_retry() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_copy = runs.clone();
+        let fails_twice = ("fails twice", move || {
+            if runs_copy.fetch_add(1, Ordering::SeqCst) + 1 > 2 {
+                ActionResult::Ok
+            } else {
+                ActionResult::Fail
+            }
+        })
+            .into_action();
+        let report = Playbook::new(
+            "retry",
+            "",
+            [],
+            [instruction(fails_twice).retry(3, Backoff::Fixed(Duration::from_millis(1)))],
+        )
+        .apply();
+        assert!(report.ok());
+        assert_eq!(report.instructions[0].attempts, 3);
+        // confirmation checks are evaluated again before retry
+        let applied = Arc::new(AtomicBool::new(false));
+        let applied_copy = applied.clone();
+        let applies_and_fails = ("applies and fails", move || {
+            applied_copy.store(true, Ordering::SeqCst);
+            ActionResult::Fail
+        })
+            .into_action();
+        let report = Playbook::new(
+            "retry-confirm",
+            "",
+            [],
+            [instruction(applies_and_fails)
+                .confirm(("is applied", move || applied.load(Ordering::SeqCst)).into_check())
+                .retry(3, Backoff::None)],
+        )
+        .apply();
+        assert!(report.ok());
+        assert_eq!(report.instructions[0].attempts, 1);
+        let report = Playbook::new(
+            "retry-fail",
+            "",
+            [],
+            [instruction(always_ok())
+                .confirm(always_no())
+                .retry(2, Backoff::None)],
+        )
+        .apply();
+        assert!(!report.ok());
+        assert_eq!(report.instructions[0].attempts, 2);
+    }
+
+    #[test]
+    fn test_external_output() {
+        let echo = || command(["sh", "-c", "echo out; echo err >&2"]);
+        let report = Playbook::new(
+            "external-output",
+            "",
+            [],
+            [
+                instruction(echo()),
+                instruction(echo()).external_output(ExternalOutput::Hide),
+                group("group", [], [instruction(echo())])
+                    .external_output(ExternalOutput::OnFailure),
+            ],
+        )
+        .with_external_output(ExternalOutput::Report)
+        .apply();
+        assert!(report.ok());
+        let output = &report.instructions[0].output;
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].cmd[0], "sh");
+        assert_eq!(output[0].stdout, "out\n".as_bytes());
+        assert_eq!(output[0].stderr, "err\n".as_bytes());
+        assert!(report.instructions[1].output.is_empty());
+        assert!(report.instructions[2].instructions[0].output.is_empty());
+        let report = Playbook::new(
+            "external-output-live",
+            "",
+            [],
+            [instruction(command(["sh", "-c", "echo live; exit 1"]))
+                .external_output(ExternalOutput::Live)],
+        )
+        .apply();
+        assert_eq!(
+            report.instructions[0].reason.as_deref(),
+            Some("process exited with error code 1")
+        );
+        let report = Playbook::new(
+            "external-output-on-failure",
+            "",
+            [],
+            [instruction(command(["sh", "-c", "echo shown; exit 1"]))],
+        )
+        .with_external_output(ExternalOutput::OnFailure)
+        .apply();
+        assert!(report.instructions[0].output.is_empty());
+    }
+
+    #[test]
+    fn test_backups() {
+        let root: PathBuf = "/tmp/pass-test-dir-111222333-test_backups".into();
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir(&root).unwrap();
+        let a = root.join("a");
+        let b = root.join("b");
+        std::fs::write(&a, "a1").unwrap();
+        std::fs::write(&b, "b1").unwrap();
+        let playbook = Playbook::new(
+            "backups",
+            "",
+            [],
+            [
+                instruction(write_file(&a, "a2")),
+                instruction(write_file(&b, "b2")).backup(false),
+            ],
+        )
+        .with_backups()
+        .with_backup_dir(root.join("backups"));
+        assert!(playbook.backup_runs().is_empty());
+        assert!(playbook.apply().ok());
+        let runs = playbook.backup_runs();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(playbook.backup_files(&runs[0]), vec![a.clone()]);
+        assert!(playbook.restore_backup("../backups").is_err());
+        assert_eq!(playbook.restore_backup(&runs[0]), Ok(vec![a.clone()]));
+        assert_eq!(std::fs::read(&a).unwrap(), b"a1");
+        assert_eq!(std::fs::read(&b).unwrap(), b"b2");
+        // second run has its own backups
+        std::fs::write(&a, "a3").unwrap();
+        assert!(playbook.apply().ok());
+        let runs = playbook.backup_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(playbook.restore_backup(&runs[1]), Ok(vec![a.clone()]));
+        assert_eq!(std::fs::read(&a).unwrap(), b"a3");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let path = "/tmp/pass-test-file-111222333-validate";
+        let playbook = Playbook::new(
+            "validate",
+            "Playbook with issues",
+            [],
+            [
+                instruction(always_ok()),
+                instruction(action("Delete file", delete_file(path)))
+                    .confirm(check("File exists", is_file(path))),
+                instruction(write_file(path, "")).confirm(path_is_missing(path)),
+                instruction(always_ok())
+                    .confirm(always_yes())
+                    .notify("restart")
+                    .depends_on("Missing"),
+                group(
+                    "group",
+                    [],
+                    [instruction(create_dir(path))
+                        .confirm(always_no())
+                        .depends_on("AlwaysOk")],
+                ),
+            ],
+        )
+        .with_handler("reload", always_ok());
+        let issues = playbook.validate();
+        let found = |severity, instruction: Option<&str>, message: &str| {
+            issues.iter().any(|i| {
+                i.severity == severity
+                    && i.instruction.as_deref() == instruction
+                    && i.message.contains(message)
+            })
+        };
+        assert!(found(
+            Severity::Warning,
+            Some("1.AlwaysOk"),
+            "no confirmation"
+        ));
+        assert!(found(
+            Severity::Warning,
+            Some("2.Delete file"),
+            "contradicts"
+        ));
+        assert!(found(Severity::Warning, Some("3.WriteFile"), "contradicts"));
+        assert!(found(Severity::Warning, Some("4.AlwaysOk"), "multiple"));
+        assert!(found(Severity::Warning, Some("4.AlwaysOk"), "always yes"));
+        assert!(found(
+            Severity::Error,
+            Some("4.AlwaysOk"),
+            "unknown handler"
+        ));
+        assert!(found(
+            Severity::Error,
+            Some("4.AlwaysOk"),
+            "unknown instruction"
+        ));
+        assert!(found(Severity::Error, Some("5.1.CreateDir"), "always no"));
+        assert!(found(Severity::Warning, Some("5.1.CreateDir"), "ignored"));
+        assert!(found(Severity::Warning, None, "never notified"));
+        assert!(!issues
+            .iter()
+            .any(|i| i.instruction.is_none() && i.is_error()));
+        assert_eq!(
+            issues[0].to_string(),
+            "warning: 1.AlwaysOk: instruction has no confirmation checks, its action is run each time playbook is applied"
+        );
+        let issues = Playbook::new("", "", [], []).validate();
+        assert!(issues
+            .iter()
+            .any(|i| i.is_error() && i.message.contains("no name")));
+        assert_eq!(issues.len(), 3);
+        let issues = Playbook::new(
+            "etc/passwd",
+            "",
+            [],
+            [instruction(always_ok()).confirm(always_yes())],
+        )
+        .validate();
+        assert!(issues
+            .iter()
+            .any(|i| i.is_error() && i.message.contains("file name")));
+        let issues = Playbook::new(
+            "cycle",
+            "",
+            [],
+            [
+                instruction(always_ok()).depends_on("AlwaysFail"),
+                instruction(always_fail()).depends_on("AlwaysOk"),
+            ],
+        )
+        .validate();
+        assert!(issues
+            .iter()
+            .any(|i| i.is_error()
+                && i.message == "dependency cycle: AlwaysOk -> AlwaysFail -> AlwaysOk"));
+        assert!(is_file_name("Install nginx"));
+        assert!(!is_file_name(".."));
+    }
 }
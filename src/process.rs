@@ -1,10 +1,22 @@
-use std::process::Command;
+use crate::interfaces::ExecContext;
+use nix::sys::signal::Signal;
+use std::{
+    cell::{Cell, RefCell},
+    io::{Read, Write},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExitCode {
     SuccessOnExit,
     ErrorOnExit,
     FailOnStart,
+    /// Process was killed, because it was running longer than allowed
+    Timeout,
 }
 
 pub struct ProcessOutput {
@@ -14,6 +26,12 @@ pub struct ProcessOutput {
 
 pub struct ProcessResult {
     pub code: ExitCode,
+    /// Numeric exit status, [None] if process not exited by itself
+    pub status: Option<i32>,
+    /// Number of signal which terminated process
+    pub signal: Option<i32>,
+    /// Error which prevented receiving status of process
+    pub error: Option<String>,
     pub output: Option<ProcessOutput>,
 }
 
@@ -21,13 +39,324 @@ impl ProcessResult {
     pub fn fail_on_start() -> Self {
         Self {
             code: ExitCode::FailOnStart,
+            status: None,
+            signal: None,
+            error: None,
             output: None,
         }
     }
 
+    pub fn timeout() -> Self {
+        Self {
+            code: ExitCode::Timeout,
+            status: None,
+            signal: None,
+            error: None,
+            output: None,
+        }
+    }
+
+    /// Process was started, but its status can't be received
+    fn fail_on_wait(e: std::io::Error) -> Self {
+        Self {
+            code: ExitCode::ErrorOnExit,
+            status: None,
+            signal: None,
+            error: Some(format!("can't wait for process: {e}")),
+            output: None,
+        }
+    }
+
+    fn exited(status: ExitStatus, output: ProcessOutput) -> Self {
+        Self {
+            code: if status.success() {
+                ExitCode::SuccessOnExit
+            } else {
+                ExitCode::ErrorOnExit
+            },
+            status: status.code(),
+            signal: status.signal(),
+            error: None,
+            output: Some(output),
+        }
+    }
+
     pub fn ok(&self) -> bool {
         self.code == ExitCode::SuccessOnExit
     }
+
+    /// Describes why process failed (exit status and last lines of stderr),
+    /// returns [None] if process succeed
+    pub fn explain(&self) -> Option<String> {
+        if let Some(error) = &self.error {
+            return Some(error.clone());
+        }
+        let status = match (self.code, self.status, self.signal) {
+            (ExitCode::SuccessOnExit, _, _) => return None,
+            (ExitCode::ErrorOnExit, Some(status), _) => {
+                format!("process exited with error code {status}")
+            }
+            (ExitCode::ErrorOnExit, None, Some(signal)) => match Signal::try_from(signal) {
+                Ok(name) => format!("process killed by signal {signal} ({name})"),
+                Err(_) => format!("process killed by signal {signal}"),
+            },
+            (ExitCode::ErrorOnExit, None, None) => "process exited with error".to_owned(),
+            (ExitCode::FailOnStart, _, _) => "process failed to start".to_owned(),
+            (ExitCode::Timeout, _, _) => "process killed on timeout".to_owned(),
+        };
+        let stderr = self
+            .output
+            .as_ref()
+            .map(|o| tail(&String::from_utf8_lossy(&o.stderr), STDERR_TAIL_LINES))
+            .unwrap_or_default();
+        if stderr.is_empty() {
+            Some(status)
+        } else {
+            Some(format!("{status}, stderr:\n{stderr}"))
+        }
+    }
+}
+
+/// Number of stderr lines shown by [ProcessResult::explain]
+const STDERR_TAIL_LINES: usize = 5;
+
+/// Last non-empty lines of text
+fn tail(text: &str, lines: usize) -> String {
+    let all: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+/// External process to run: command with its arguments and environment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessSpec {
+    pub cmd: Vec<String>,
+    /// Environment variables set for process
+    pub env: Vec<(String, String)>,
+    /// If `true` process does not inherit environment variables
+    pub env_clear: bool,
+    /// Data written into stdin of process, stdin is empty if [None]
+    pub stdin: Option<Vec<u8>>,
+    /// Working directory of process, by default current directory is used
+    pub dir: Option<PathBuf>,
+    /// Name of user process runs as
+    pub user: Option<String>,
+    /// Name of group process runs as, by default primary group of
+    /// [ProcessSpec::user] is used
+    pub group: Option<String>,
+    /// File mode creation mask of process
+    pub umask: Option<u32>,
+}
+
+impl ProcessSpec {
+    pub fn new(cmd: Vec<String>) -> Self {
+        Self {
+            cmd,
+            ..Default::default()
+        }
+    }
+
+    /// Sets environment variable, can be used multiple times
+    pub fn env<Name, Value>(mut self, name: Name, value: Value) -> Self
+    where
+        Name: Into<String>,
+        Value: Into<String>,
+    {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Process will not inherit environment variables, only variables set with
+    /// [ProcessSpec::env] are used
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self
+    }
+
+    pub fn stdin<Data>(mut self, data: Data) -> Self
+    where
+        Data: Into<Vec<u8>>,
+    {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// Sets working directory of process, relative paths of command are
+    /// resolved from it. By default working directory of execution context is
+    /// used (see [ExecContext])
+    pub fn dir<Dir>(mut self, dir: Dir) -> Self
+    where
+        Dir: Into<PathBuf>,
+    {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Runs process as provided user (requires root), supplementary groups
+    /// of current user are dropped
+    pub fn user<Name>(mut self, user: Name) -> Self
+    where
+        Name: Into<String>,
+    {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Runs process as provided group (requires root)
+    pub fn group<Name>(mut self, group: Name) -> Self
+    where
+        Name: Into<String>,
+    {
+        self.group = Some(group.into());
+        self
+    }
+
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = Some(umask);
+        self
+    }
+
+    /// Copy of spec with working directory resolved against directory of
+    /// execution context
+    pub(crate) fn in_context(&self, ctx: &ExecContext) -> Self {
+        let mut spec = self.clone();
+        spec.dir = match &self.dir {
+            Some(dir) => Some(ctx.resolve(dir)),
+            None => ctx.dir().map(|d| d.to_path_buf()),
+        };
+        spec
+    }
+
+    /// Prepares command, returns [None] if user or group does not exist
+    fn command(&self) -> Option<Command> {
+        let (cmd, args) = self.cmd.split_first()?;
+        let mut command = Command::new(cmd);
+        command.args(args);
+        if self.env_clear {
+            command.env_clear();
+        }
+        command.envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        let user = match &self.user {
+            Some(name) => Some(nix::unistd::User::from_name(name).ok()??),
+            None => None,
+        };
+        let gid = match (&self.group, &user) {
+            (Some(name), _) => Some(nix::unistd::Group::from_name(name).ok()??.gid),
+            (None, Some(user)) => Some(user.gid),
+            (None, None) => None,
+        };
+        if let Some(gid) = gid {
+            command.gid(gid.as_raw());
+        }
+        if let Some(user) = user {
+            command.uid(user.uid.as_raw());
+        }
+        if let Some(umask) = self.umask {
+            let mode = nix::sys::stat::Mode::from_bits_truncate(umask);
+            // SAFETY: `umask` is async-signal-safe and does not allocate
+            unsafe {
+                command.pre_exec(move || {
+                    nix::sys::stat::umask(mode);
+                    Ok(())
+                });
+            }
+        }
+        Some(command)
+    }
+}
+
+/// init [ProcessSpec]
+pub fn process_spec<Cmd, Arg>(cmd: Cmd) -> ProcessSpec
+where
+    Arg: Into<String>,
+    Cmd: Into<Vec<Arg>>,
+{
+    ProcessSpec::new(norm_cmd(cmd))
+}
+
+impl<Arg, const N: usize> From<[Arg; N]> for ProcessSpec
+where
+    Arg: Into<String>,
+{
+    fn from(value: [Arg; N]) -> Self {
+        process_spec(value)
+    }
+}
+
+impl<Arg> From<Vec<Arg>> for ProcessSpec
+where
+    Arg: Into<String>,
+{
+    fn from(value: Vec<Arg>) -> Self {
+        process_spec(value)
+    }
+}
+
+/// Command and output of process started with [run] inside of
+/// [capture_output]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessLog {
+    pub cmd: Vec<String>,
+    pub code: ExitCode,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    static LIVE_OUTPUT: Cell<bool> = const { Cell::new(false) };
+    static CAPTURED: RefCell<Option<Vec<ProcessLog>>> = const { RefCell::new(None) };
+}
+
+/// Runs function printing output (stdout and stderr) of processes started
+/// with [run] inside of it into stderr while they are running
+pub fn with_live_output<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = LIVE_OUTPUT.with(|l| l.replace(true));
+    let result = f();
+    LIVE_OUTPUT.with(|l| l.set(previous));
+    result
+}
+
+/// Runs function collecting output of all processes started with [run] inside
+/// of it
+pub fn capture_output<F, R>(f: F) -> (R, Vec<ProcessLog>)
+where
+    F: FnOnce() -> R,
+{
+    let previous = CAPTURED.with(|c| c.replace(Some(vec![])));
+    let result = f();
+    let captured = CAPTURED.with(|c| c.replace(previous)).unwrap_or_default();
+    // outer capture gets output of processes captured inside of it too
+    CAPTURED.with(|c| {
+        if let Some(outer) = c.borrow_mut().as_mut() {
+            outer.extend(captured.iter().cloned());
+        }
+    });
+    (result, captured)
+}
+
+/// Returns deadline set with [with_deadline] for current thread
+pub fn deadline() -> Option<Instant> {
+    DEADLINE.with(|d| d.get())
+}
+
+/// Runs function with deadline, processes started with [run] inside of it are
+/// killed when deadline reached. Nested deadlines can only make it earlier
+pub fn with_deadline<F, R>(deadline: Instant, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = self::deadline();
+    let deadline = previous.map_or(deadline, |p| p.min(deadline));
+    DEADLINE.with(|d| d.set(Some(deadline)));
+    let result = f();
+    DEADLINE.with(|d| d.set(previous));
+    result
 }
 
 // todo: try `Arg: AsRef<str>`
@@ -39,30 +368,144 @@ where
     cmd.into().into_iter().map(|c| c.into()).collect()
 }
 
+/// Runs command and waits for it to finish, if called inside of
+/// [with_deadline] process is killed when deadline reached
 pub fn run(cmd: &[String]) -> ProcessResult {
-    let Some((cmd, args)) = cmd.split_first() else {
-        return ProcessResult::fail_on_start();
-    };
-    let Ok(output) = Command::new(cmd).args(args).output() else {
+    run_spec(&ProcessSpec::new(cmd.to_vec()))
+}
+
+/// Runs process and waits for it to finish, same as [run]
+pub fn run_spec(spec: &ProcessSpec) -> ProcessResult {
+    run_until(spec, deadline())
+}
+
+/// Runs command, killing it if it runs longer than `timeout`
+pub fn run_timeout(cmd: &[String], timeout: Duration) -> ProcessResult {
+    let timeout_at = Instant::now() + timeout;
+    run_until(
+        &ProcessSpec::new(cmd.to_vec()),
+        Some(deadline().map_or(timeout_at, |d| d.min(timeout_at))),
+    )
+}
+
+fn run_until(spec: &ProcessSpec, deadline: Option<Instant>) -> ProcessResult {
+    let result = run_process(spec, deadline);
+    CAPTURED.with(|c| {
+        if let Some(captured) = c.borrow_mut().as_mut() {
+            let output = result.output.as_ref();
+            captured.push(ProcessLog {
+                cmd: spec.cmd.clone(),
+                code: result.code,
+                stdout: output.map(|o| o.stdout.clone()).unwrap_or_default(),
+                stderr: output.map(|o| o.stderr.clone()).unwrap_or_default(),
+            });
+        }
+    });
+    result
+}
+
+fn run_process(spec: &ProcessSpec, deadline: Option<Instant>) -> ProcessResult {
+    let Some(mut command) = spec.command() else {
         return ProcessResult::fail_on_start();
     };
-    if output.status.success() {
-        ProcessResult {
-            code: ExitCode::SuccessOnExit,
-            output: Some(ProcessOutput {
+    let live = LIVE_OUTPUT.with(|l| l.get());
+    if deadline.is_none() && !live && spec.stdin.is_none() {
+        let Ok(output) = command.output() else {
+            return ProcessResult::fail_on_start();
+        };
+        return ProcessResult::exited(
+            output.status,
+            ProcessOutput {
                 stdout: output.stdout,
                 stderr: output.stderr,
-            }),
+            },
+        );
+    };
+    command
+        .stdin(if spec.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if deadline.is_some() {
+        // process is started in its own group, so all its children can be
+        // killed on timeout
+        command.process_group(0);
+    }
+    let Ok(mut child) = command.spawn() else {
+        return ProcessResult::fail_on_start();
+    };
+    if let (Some(mut stdin), Some(data)) = (child.stdin.take(), spec.stdin.clone()) {
+        // written in separate thread, process can wait for its output to be
+        // read before reading all input
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&data);
+        });
+    }
+    let stdout = read_pipe(child.stdout.take(), live);
+    let stderr = read_pipe(child.stderr.take(), live);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if deadline.is_none_or(|d| Instant::now() < d) => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(None) => {
+                let group = nix::unistd::Pid::from_raw(child.id() as i32);
+                let _ = nix::sys::signal::killpg(group, Signal::SIGKILL);
+                let _ = child.wait();
+                return ProcessResult::timeout();
+            }
+            Err(e) => {
+                let _ = child.kill();
+                return ProcessResult::fail_on_wait(e);
+            }
         }
-    } else {
-        ProcessResult {
-            code: ExitCode::ErrorOnExit,
-            output: Some(ProcessOutput {
-                stdout: output.stdout,
-                stderr: output.stderr,
-            }),
+    };
+    // pipes can be kept open by children which left process group, not
+    // waiting for them forever
+    let pipe_timeout = Duration::from_secs(1);
+    ProcessResult::exited(
+        status,
+        ProcessOutput {
+            stdout: stdout.recv_timeout(pipe_timeout).unwrap_or_default(),
+            stderr: stderr.recv_timeout(pipe_timeout).unwrap_or_default(),
+        },
+    )
+}
+
+/// Reads pipe in separate thread, sends all data when pipe is closed, if
+/// `live` is set data is also printed into stderr as it is read
+fn read_pipe<R>(pipe: Option<R>, live: bool) -> mpsc::Receiver<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut data = vec![];
+        if let Some(mut pipe) = pipe {
+            let mut buffer = [0; 4096];
+            loop {
+                let n = match pipe.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                if live {
+                    // output is best effort, same as for story
+                    let mut stderr = std::io::stderr();
+                    let _ = stderr.write_all(&buffer[..n]);
+                    let _ = stderr.flush();
+                }
+                data.extend_from_slice(&buffer[..n]);
+            }
         }
-    }
+        let _ = sender.send(data);
+    });
+    receiver
 }
 
 #[cfg(test)]
@@ -85,10 +528,109 @@ mod test {
             matches!(result.code, ExitCode::ErrorOnExit);
             assert!(!result.ok());
         }
+        {
+            let result = run(&norm_cmd(["sh", "-c", "echo 1 >&2; echo 2 >&2; exit 1"]));
+            assert_eq!(
+                result.explain().as_deref(),
+                Some("process exited with error code 1, stderr:\n1\n2")
+            );
+            assert_eq!(run(&norm_cmd(["true"])).explain(), None);
+        }
+        {
+            let result = run(&norm_cmd(["sh", "-c", "exit 3"]));
+            assert_eq!((result.status, result.signal), (Some(3), None));
+            let result = run(&norm_cmd(["sh", "-c", "kill -TERM $$"]));
+            assert_eq!((result.status, result.signal), (None, Some(15)));
+            assert_eq!(
+                result.explain().as_deref(),
+                Some("process killed by signal 15 (SIGTERM)")
+            );
+        }
         {
             let result = run(&norm_cmd(["aaabbb-not-a-command-bbbaaa"]));
             matches!(result.code, ExitCode::FailOnStart);
             assert!(!result.ok());
         }
     }
+
+    #[test]
+    fn test_run_spec() {
+        let spec = process_spec(["sh", "-c", "echo $PASS_A-$PASS_B; pwd; cat; umask"])
+            .env("PASS_A", "1")
+            .env("PASS_B", "2")
+            .stdin("input\n")
+            .dir("/tmp")
+            .umask(0o027);
+        let result = run_spec(&spec);
+        assert!(result.ok());
+        assert_eq!(
+            result.output.unwrap().stdout,
+            "1-2\n/tmp\ninput\n0027\n".as_bytes()
+        );
+
+        let spec = process_spec(["sh", "-c", "echo -n $HOME"]).env_clear();
+        assert_eq!(run_spec(&spec).output.unwrap().stdout, b"");
+
+        let spec = process_spec(["true"]).user("aaabbb-not-a-user-bbbaaa");
+        assert!(matches!(run_spec(&spec).code, ExitCode::FailOnStart));
+        assert!(!run_spec(&spec).ok());
+    }
+
+    #[test]
+    fn test_capture_output() {
+        let (outer, captured) = capture_output(|| {
+            run(&norm_cmd(["echo", "1"]));
+            let (result, captured) = capture_output(|| {
+                with_live_output(|| run(&norm_cmd(["sh", "-c", "echo 2; echo 3 >&2"])))
+            });
+            assert_eq!(result.output.unwrap().stdout, "2\n".as_bytes());
+            assert_eq!(captured.len(), 1);
+            assert_eq!(captured[0].stderr, "3\n".as_bytes());
+            "outer"
+        });
+        assert_eq!(outer, "outer");
+        let commands: Vec<_> = captured.iter().map(|p| p.cmd[0].as_str()).collect();
+        assert_eq!(commands, vec!["echo", "sh"]);
+        assert_eq!(captured[1].code, ExitCode::SuccessOnExit);
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        {
+            let result = run_timeout(&norm_cmd(["echo", "1"]), Duration::from_secs(5));
+            assert!(result.ok());
+            assert_eq!(result.output.unwrap().stdout, "1\n".as_bytes());
+        }
+        {
+            let result = run_timeout(&norm_cmd(["false"]), Duration::from_secs(5));
+            assert_eq!(result.code, ExitCode::ErrorOnExit);
+        }
+        {
+            let started = Instant::now();
+            let result = run_timeout(
+                &norm_cmd(["sh", "-c", "sleep 10; echo 1"]),
+                Duration::from_millis(200),
+            );
+            assert_eq!(result.code, ExitCode::Timeout);
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+        {
+            let started = Instant::now();
+            let result = with_deadline(Instant::now() + Duration::from_millis(200), || {
+                run(&norm_cmd(["sleep", "10"]))
+            });
+            assert_eq!(result.code, ExitCode::Timeout);
+            assert!(started.elapsed() < Duration::from_secs(5));
+            assert_eq!(deadline(), None);
+        }
+        {
+            // failed wait is not reported as timeout
+            let result = ProcessResult::fail_on_wait(std::io::Error::other("no child"));
+            assert_eq!(result.code, ExitCode::ErrorOnExit);
+            assert_eq!(
+                result.explain().as_deref(),
+                Some("can't wait for process: no child")
+            );
+        }
+    }
 }
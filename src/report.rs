@@ -0,0 +1,301 @@
+//! Structured results of [Playbook](crate::Playbook) runs
+
+use std::time::Duration;
+
+use crate::{interfaces::ActionResult, process::ProcessLog};
+
+/// Result of single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// Name of check
+    pub name: String,
+    pub yes: bool,
+    /// Reason why check is *no*, if check explains it
+    pub reason: Option<String>,
+}
+
+impl CheckResult {
+    pub fn new<Name>(name: Name, yes: bool) -> Self
+    where
+        Name: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            yes,
+            reason: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+}
+
+/// Reason why instruction was skipped without evaluating its checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Instruction is outside of instructions selected to be applied (run is
+    /// resumed or started from later instruction, or only one instruction is
+    /// applied)
+    Resume,
+    /// Some of conditions of instruction are *no*
+    ConditionNotMet,
+    /// Instruction filtered out by tags (see
+    /// [TagFilter](crate::playbook::TagFilter))
+    Filter,
+}
+
+impl SkipReason {
+    /// Short human readable description of reason
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SkipReason::Resume => "skipped due to resume",
+            SkipReason::ConditionNotMet => "skipped: condition not met",
+            SkipReason::Filter => "skipped by filter",
+        }
+    }
+}
+
+/// Result of applying single instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionReport {
+    /// Name of instruction action
+    pub name: String,
+    /// Environment checks, empty if not checked
+    pub env: Vec<CheckResult>,
+    /// Confirmation checks before action
+    pub confirm_before: Vec<CheckResult>,
+    /// Confirmation checks after action, empty if action was not run
+    pub confirm_after: Vec<CheckResult>,
+    /// `true` if action skipped, because all confirmations are *yes*
+    pub already_applied: bool,
+    /// `true` if action was run
+    pub ran: bool,
+    /// Number of times action was run
+    pub attempts: usize,
+    /// Reason why instruction was skipped, [None] if it was not skipped
+    pub skipped: Option<SkipReason>,
+    /// Result of instruction, [None] if instruction was not reached (previous
+    /// instruction failed) or skipped
+    pub result: Option<ActionResult>,
+    /// Reason of last failure of action, if action explains it
+    pub reason: Option<String>,
+    /// Output of external processes run by action (in all attempts), stored
+    /// only with [ExternalOutput::Report](crate::playbook::ExternalOutput::Report)
+    pub output: Vec<ProcessLog>,
+    pub duration: Duration,
+    /// Result of reverting instruction, [None] if it was not rolled back
+    pub rolled_back: Option<ActionResult>,
+    /// Reason of rollback failure, if rollback action explains it
+    pub rollback_reason: Option<String>,
+    /// Reports for instructions of group, empty if instruction is not group
+    pub instructions: Vec<InstructionReport>,
+}
+
+impl InstructionReport {
+    pub fn new<Name>(name: Name) -> Self
+    where
+        Name: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            env: vec![],
+            confirm_before: vec![],
+            confirm_after: vec![],
+            already_applied: false,
+            ran: false,
+            attempts: 0,
+            skipped: None,
+            result: None,
+            reason: None,
+            output: vec![],
+            duration: Duration::ZERO,
+            rolled_back: None,
+            rollback_reason: None,
+            instructions: vec![],
+        }
+    }
+}
+
+/// Result of running handler of playbook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerReport {
+    /// Name of handler
+    pub name: String,
+    /// Result of handler action, [None] if handler was not notified (or
+    /// playbook failed before handlers)
+    pub result: Option<ActionResult>,
+    /// Reason of handler failure, if action explains it
+    pub reason: Option<String>,
+}
+
+/// Result of [Playbook::apply](crate::Playbook::apply)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// Name of applied playbook
+    pub name: String,
+    pub result: ActionResult,
+    /// Playbook environment checks
+    pub env: Vec<CheckResult>,
+    /// Reports for all playbook instructions in order
+    pub instructions: Vec<InstructionReport>,
+    /// Reports for all playbook handlers in order of registration
+    pub handlers: Vec<HandlerReport>,
+    pub duration: Duration,
+}
+
+impl ApplyReport {
+    pub fn ok(&self) -> bool {
+        self.result.ok()
+    }
+}
+
+/// Status of instruction found by [Playbook::check](crate::Playbook::check)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionStatus {
+    /// All confirmation checks are *yes*, action would be skipped
+    AlreadyApplied,
+    /// Action would be applied (all confirmation checks are *no* or there is
+    /// no confirmation checks)
+    WouldApply,
+    /// Environment checks of instruction failed, action can't be applied
+    BlockedByEnv,
+    /// Some of conditions of instruction are *no*, instruction would be
+    /// skipped
+    ConditionNotMet,
+    /// Instruction filtered out by tags, it would be skipped
+    SkippedByFilter,
+    /// Instruction is outside of instructions selected to be applied (see
+    /// [Start](crate::playbook::Start)), it would be skipped
+    SkippedByResume,
+    /// Confirmation checks are mixed (some *yes*, some *no*), applying
+    /// instruction would fail
+    ConfirmationMixed,
+}
+
+impl InstructionStatus {
+    /// Short human readable description of status
+    pub fn describe(&self) -> &'static str {
+        match self {
+            InstructionStatus::AlreadyApplied => "already applied",
+            InstructionStatus::WouldApply => "would apply",
+            InstructionStatus::BlockedByEnv => "blocked by env",
+            InstructionStatus::ConfirmationMixed => "confirmation mixed",
+            InstructionStatus::ConditionNotMet => "condition not met",
+            InstructionStatus::SkippedByFilter => "skipped by filter",
+            InstructionStatus::SkippedByResume => "skipped due to resume",
+        }
+    }
+
+    /// Status of group with provided instructions, group fails if any of
+    /// instructions fails, and would apply if any of them would apply
+    pub fn group(instructions: &[InstructionCheck]) -> Self {
+        let statuses = || instructions.iter().map(|i| i.status);
+        statuses()
+            .find(|s| {
+                matches!(
+                    s,
+                    InstructionStatus::BlockedByEnv | InstructionStatus::ConfirmationMixed
+                )
+            })
+            .or_else(|| statuses().find(|s| *s == InstructionStatus::WouldApply))
+            .unwrap_or(InstructionStatus::AlreadyApplied)
+    }
+}
+
+/// Result of checking single instruction without applying it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionCheck {
+    /// Name of instruction action
+    pub name: String,
+    pub status: InstructionStatus,
+    /// Why instruction would apply: reason of first *no* confirmation check
+    pub reason: Option<String>,
+    /// Results for instructions of group, empty if instruction is not group
+    pub instructions: Vec<InstructionCheck>,
+}
+
+/// Result of [Playbook::check](crate::Playbook::check)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Name of checked playbook
+    pub name: String,
+    /// `true` if all playbook environment checks are *yes*, if `false`
+    /// instructions are not checked
+    pub env_ok: bool,
+    pub instructions: Vec<InstructionCheck>,
+}
+
+impl CheckReport {
+    /// Returns `true` if applying playbook would change system or can't be
+    /// done (environment checks failed, instruction is blocked)
+    pub fn drift(&self) -> bool {
+        !self.env_ok
+            || self.instructions.iter().any(|i| {
+                !matches!(
+                    i.status,
+                    InstructionStatus::AlreadyApplied
+                        | InstructionStatus::ConditionNotMet
+                        | InstructionStatus::SkippedByFilter
+                        | InstructionStatus::SkippedByResume
+                )
+            })
+    }
+
+    /// Number of instructions with provided status
+    pub fn count(&self, status: InstructionStatus) -> usize {
+        self.instructions
+            .iter()
+            .filter(|i| i.status == status)
+            .count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Playbook can be applied, but it is probably not what was intended
+    Warning,
+    /// Playbook can't be applied correctly
+    Error,
+}
+
+/// Issue found by [Playbook::validate](crate::Playbook::validate)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Number and name of instruction as shown in story (eg. `2.1.WriteFile`
+    /// for first instruction of group), [None] if issue is in playbook itself
+    pub instruction: Option<String>,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn new<Message>(severity: Severity, instruction: Option<String>, message: Message) -> Self
+    where
+        Message: Into<String>,
+    {
+        Self {
+            severity,
+            instruction,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match &self.instruction {
+            Some(instruction) => write!(f, "{severity}: {instruction}: {}", self.message),
+            None => write!(f, "{severity}: {}", self.message),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::io::Write;
 use terminal_size::{terminal_size, Width};
 
+use crate::process::ProcessLog;
+
 fn name_ok_fail(flag: bool) -> &'static str {
     if flag {
         "ok"
@@ -46,25 +48,442 @@ fn print_wrapped(prefix: &str, border: &str, text: &str) {
     }
 }
 
-#[derive(Clone)]
+/// Format of playbook output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable tree of sections and checklists
+    #[default]
+    Text,
+    /// One JSON object per line for each event (section start/end, checklist
+    /// item, note, process result)
+    Json,
+}
+
+/// Kind of story section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectionKind {
+    Section,
+    Checklist,
+    Process,
+}
+
+impl SectionKind {
+    fn name(&self) -> &'static str {
+        match self {
+            SectionKind::Section => "section",
+            SectionKind::Checklist => "checklist",
+            SectionKind::Process => "process",
+        }
+    }
+}
+
+/// What is being done with playbook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunMode {
+    Apply,
+    Check,
+}
+
+/// Where note is placed inside of section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NotePlace {
+    /// Right after checklist title, before items
+    ChecklistTitle,
+    /// After checklist items
+    Checklist,
+    /// Note for section itself
+    Section,
+    /// Reason why checklist item is *no*, right after item
+    ItemReason,
+    /// Reason of failed process, right after its result
+    ProcessReason,
+}
+
+/// Destination of story events, [StoryFormatter] keeps track of sections and
+/// passes events to sink which renders them
+pub(crate) trait StorySink {
+    fn playbook_header(&mut self, mode: RunMode, header: &str);
+    fn playbook_result(&mut self, header: &str, ok: bool, status: &str);
+    fn section_start(&mut self, path: &[String], kind: SectionKind);
+    fn section_end(&mut self, path: &[String], kind: SectionKind, ok: bool);
+    fn checklist_item(&mut self, path: &[String], ok: bool, i: usize, title: &str);
+    fn note(&mut self, path: &[String], place: NotePlace, note: &str);
+    /// Output of external processes is printed while process section is open
+    fn live_output(&mut self, path: &[String]);
+    fn process_output(&mut self, path: &[String], process: &ProcessLog);
+}
+
+fn text_section_name(path: &[String]) -> String {
+    path.iter()
+        .map(|s| {
+            let square_brackets = s.contains('.');
+            let mut tokens = vec![];
+            if square_brackets {
+                tokens.push("[".to_owned());
+            }
+            tokens.push(s.clone());
+            if square_brackets {
+                tokens.push("]".to_owned());
+            }
+            tokens.join("")
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Prints story as human readable text into stdout
+pub(crate) struct TextSink {
+    next_is_separator: bool,
+    /// Line of open process was broken by output of external processes
+    live_output: bool,
+}
+
+impl TextSink {
+    pub fn new() -> Self {
+        Self {
+            next_is_separator: false,
+            live_output: false,
+        }
+    }
+
+    fn put_separator(&mut self) {
+        if self.next_is_separator {
+            self.next_is_separator = false;
+            println!();
+        }
+    }
+}
+
+impl StorySink for TextSink {
+    fn playbook_header(&mut self, mode: RunMode, header: &str) {
+        let mode = match mode {
+            RunMode::Apply => "Applying",
+            RunMode::Check => "Checking",
+        };
+        println!("{mode} playbook: {}", header);
+        self.next_is_separator = true;
+    }
+
+    fn playbook_result(&mut self, header: &str, _ok: bool, status: &str) {
+        println!();
+        print!("{}", header);
+        println!();
+        println!();
+        print!(" {}", status.to_uppercase());
+        println!();
+        println!();
+    }
+
+    fn section_start(&mut self, path: &[String], kind: SectionKind) {
+        match kind {
+            SectionKind::Section => {}
+            SectionKind::Checklist => {
+                // reset `separator` flag, not needed before checklist
+                self.next_is_separator = false;
+                println!(" _");
+                print_wrapped("|", "|", &text_section_name(path));
+                println!();
+                println!("|");
+            }
+            SectionKind::Process => {
+                self.put_separator();
+                print!("{}", text_section_name(path));
+                print!("|> ");
+                print!("...applying");
+                // trying to flush, but not hard, ignoring if error
+                let _ = std::io::stdout().flush();
+            }
+        }
+    }
+
+    fn section_end(&mut self, path: &[String], kind: SectionKind, ok: bool) {
+        match kind {
+            SectionKind::Section => {
+                self.put_separator();
+                println!("{}|> {}", text_section_name(path), name_ok_fail(ok));
+            }
+            SectionKind::Checklist => {
+                println!("|");
+                println!("|> {}", name_ok_fail(ok));
+                self.next_is_separator = true;
+            }
+            SectionKind::Process => {
+                let result_name = if ok { "...done!" } else { "...FAIL!" };
+                if self.live_output {
+                    self.live_output = false;
+                    print!("{}|> ", text_section_name(path));
+                }
+                println!("{result_name}");
+            }
+        }
+    }
+
+    // todo: use box-drawing characters?
+    // todo: add colors (if terminal supports)?
+    fn checklist_item(&mut self, _path: &[String], ok: bool, i: usize, title: &str) {
+        let prefix = format!("|-[{:^3}] ", name_y_n(ok));
+        let title = format!("{i}.{title}");
+        print_wrapped(&prefix, "|", &title);
+        println!();
+    }
+
+    fn note(&mut self, path: &[String], place: NotePlace, note: &str) {
+        match place {
+            NotePlace::ChecklistTitle => {
+                let note = format!("*{note}*");
+                print_wrapped("| ", "|", &note);
+                println!();
+                println!("|");
+            }
+            NotePlace::Checklist => {
+                println!("|");
+                let note = format!("*{note}*");
+                print_wrapped("| ", "|", &note);
+                println!();
+            }
+            NotePlace::Section => {
+                self.put_separator();
+                println!("{}|> *{note}*", text_section_name(path));
+            }
+            NotePlace::ItemReason => {
+                for line in note.lines() {
+                    print_wrapped("|        ", "|        ", line);
+                    println!();
+                }
+            }
+            NotePlace::ProcessReason => {
+                for line in note.lines() {
+                    print_wrapped("  ", "  ", line);
+                    println!();
+                }
+            }
+        }
+    }
+
+    fn live_output(&mut self, _path: &[String]) {
+        self.live_output = true;
+        println!();
+    }
+
+    fn process_output(&mut self, _path: &[String], process: &ProcessLog) {
+        print_wrapped("  $ ", "    ", &process.cmd.join(" "));
+        println!();
+        for line in String::from_utf8_lossy(&process.stdout).lines() {
+            print_wrapped("  | ", "  | ", line);
+            println!();
+        }
+        for line in String::from_utf8_lossy(&process.stderr).lines() {
+            print_wrapped("  ! ", "  ! ", line);
+            println!();
+        }
+    }
+}
+
+/// Escapes string and wraps it into quotes, making it valid JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_path(path: &[String]) -> String {
+    let items: Vec<String> = path.iter().map(|s| json_string(s)).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Writes story as JSON lines, one object per event
+pub(crate) struct JsonSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    fn event(&mut self, event: &str, fields: &[(&str, String)]) {
+        let mut line = format!("{{\"event\":{}", json_string(event));
+        for (key, value) in fields {
+            line.push_str(&format!(",{}:{}", json_string(key), value));
+        }
+        line.push('}');
+        // output is best effort, same as for text output
+        let _ = writeln!(self.out, "{line}");
+        let _ = self.out.flush();
+    }
+}
+
+impl<W: Write> StorySink for JsonSink<W> {
+    fn playbook_header(&mut self, mode: RunMode, header: &str) {
+        let mode = match mode {
+            RunMode::Apply => "apply",
+            RunMode::Check => "check",
+        };
+        self.event(
+            "playbook_start",
+            &[
+                ("mode", json_string(mode)),
+                ("playbook", json_string(header)),
+            ],
+        );
+    }
+
+    fn playbook_result(&mut self, header: &str, ok: bool, status: &str) {
+        self.event(
+            "playbook_end",
+            &[
+                ("playbook", json_string(header)),
+                ("ok", ok.to_string()),
+                ("status", json_string(status)),
+            ],
+        );
+    }
+
+    fn section_start(&mut self, path: &[String], kind: SectionKind) {
+        self.event(
+            "section_start",
+            &[
+                ("path", json_path(path)),
+                ("kind", json_string(kind.name())),
+            ],
+        );
+    }
+
+    fn section_end(&mut self, path: &[String], kind: SectionKind, ok: bool) {
+        let event = if kind == SectionKind::Process {
+            "process_result"
+        } else {
+            "section_end"
+        };
+        self.event(
+            event,
+            &[
+                ("path", json_path(path)),
+                ("kind", json_string(kind.name())),
+                ("ok", ok.to_string()),
+            ],
+        );
+    }
+
+    fn checklist_item(&mut self, path: &[String], ok: bool, i: usize, title: &str) {
+        self.event(
+            "checklist_item",
+            &[
+                ("path", json_path(path)),
+                ("index", i.to_string()),
+                ("name", json_string(title)),
+                ("yes", ok.to_string()),
+            ],
+        );
+    }
+
+    fn note(&mut self, path: &[String], place: NotePlace, note: &str) {
+        if let NotePlace::ItemReason | NotePlace::ProcessReason = place {
+            self.event(
+                "reason",
+                &[("path", json_path(path)), ("reason", json_string(note))],
+            );
+            return;
+        }
+        self.event(
+            "note",
+            &[("path", json_path(path)), ("note", json_string(note))],
+        );
+    }
+
+    fn live_output(&mut self, path: &[String]) {
+        self.event("live_output", &[("path", json_path(path))]);
+    }
+
+    fn process_output(&mut self, path: &[String], process: &ProcessLog) {
+        self.event(
+            "process_output",
+            &[
+                ("path", json_path(path)),
+                ("cmd", json_path(&process.cmd)),
+                (
+                    "stdout",
+                    json_string(&String::from_utf8_lossy(&process.stdout)),
+                ),
+                (
+                    "stderr",
+                    json_string(&String::from_utf8_lossy(&process.stderr)),
+                ),
+            ],
+        );
+    }
+}
+
 pub(crate) struct StoryFormatter {
     section_stack: Vec<String>,
-    next_is_separator: bool,
+    kind_stack: Vec<SectionKind>,
+    sink: Box<dyn StorySink>,
 }
 
 impl StoryFormatter {
-    pub fn new() -> Self {
+    pub fn new(output: OutputFormat) -> Self {
+        let sink: Box<dyn StorySink> = match output {
+            OutputFormat::Text => Box::new(TextSink::new()),
+            OutputFormat::Json => Box::new(JsonSink::new(std::io::stdout())),
+        };
         Self {
             section_stack: vec![],
-            next_is_separator: false,
+            kind_stack: vec![],
+            sink,
         }
     }
 
-    pub fn push<Name>(&mut self, section_name: Name)
+    /// Number of currently open sections
+    pub fn depth(&self) -> usize {
+        self.section_stack.len()
+    }
+
+    /// Opens new section, it must be closed with [StoryFormatter::close]
+    pub fn open<Name>(&mut self, section_name: Name, kind: SectionKind)
     where
         Name: Into<String>,
     {
         self.section_stack.push(section_name.into());
+        self.kind_stack.push(kind);
+        self.sink.section_start(&self.section_stack, kind);
+    }
+
+    /// Closes last open section
+    pub fn close(&mut self, ok: bool) {
+        self.close_with_reason(ok, None);
+    }
+
+    /// Closes last open section, printing reason of failure after it
+    pub fn close_with_reason(&mut self, ok: bool, reason: Option<&str>) {
+        let Some(kind) = self.kind_stack.last() else {
+            return;
+        };
+        self.sink.section_end(&self.section_stack, *kind, ok);
+        if let Some(reason) = reason {
+            self.sink
+                .note(&self.section_stack, NotePlace::ProcessReason, reason);
+        }
+        self.section_stack.pop();
+        self.kind_stack.pop();
+    }
+
+    /// Closes sections until there is `depth` open sections left
+    pub fn close_to(&mut self, depth: usize, ok: bool) {
+        while self.depth() > depth {
+            self.close(ok);
+        }
     }
 
     pub fn section<Name, F>(&mut self, section_name: Name, section_fn: F) -> Result<(), ()>
@@ -72,136 +491,107 @@ impl StoryFormatter {
         Name: Into<String>,
         F: FnOnce(&mut Self) -> Result<(), ()>,
     {
-        self.push(section_name);
+        self.open(section_name, SectionKind::Section);
         let result = section_fn(self);
-        self.section_result(result.is_ok());
+        self.close(result.is_ok());
         result
     }
 
-    fn put_separator(&mut self) {
-        if self.next_is_separator {
-            self.next_is_separator = false;
-            println!();
-        }
-    }
-
-    fn section_name(&self) -> String {
-        self.section_stack
-            .iter()
-            .map(|s| {
-                let square_brackets = s.contains('.');
-                let mut tokens = vec![];
-                if square_brackets {
-                    tokens.push("[".to_owned());
-                }
-                tokens.push(s.clone());
-                if square_brackets {
-                    tokens.push("]".to_owned());
-                }
-                tokens.join("")
-            })
-            .collect::<Vec<String>>()
-            .join(".")
-    }
-
     pub fn checklist<Name, F>(&mut self, title: Name, checklist_fn: F) -> Result<(), ()>
     where
         Name: Into<String>,
         F: FnOnce(&mut Self) -> Result<(), ()>,
     {
-        // reset `separator` flag, not needed before checklist
-        self.next_is_separator = false;
-        self.push(title);
-        self.checklist_title();
+        self.open(title, SectionKind::Checklist);
         let result = checklist_fn(self);
-        self.checklist_result(result.is_ok());
+        self.close(result.is_ok());
         result
     }
 
-    pub fn checklist_title(&self) {
-        println!(" _");
+    pub fn checklist_item(&mut self, ok: bool, i: usize, title: &str) {
+        self.sink.checklist_item(&self.section_stack, ok, i, title);
+    }
 
-        print_wrapped("|", "|", &self.section_name());
-        println!();
+    /// Prints reason why last checklist item is *no*
+    pub fn checklist_item_reason(&mut self, reason: &str) {
+        self.sink
+            .note(&self.section_stack, NotePlace::ItemReason, reason);
+    }
 
-        println!("|");
+    pub fn checklist_title_note(&mut self, note: &str) {
+        self.sink
+            .note(&self.section_stack, NotePlace::ChecklistTitle, note);
     }
 
-    // todo: use box-drawing characters?
-    // todo: add colors (if terminal supports)?
-    pub fn checklist_item(&self, ok: bool, i: usize, title: &str) {
-        let prefix = format!("|-[{:^3}] ", name_y_n(ok));
-        let title = format!("{i}.{title}");
-        print_wrapped(&prefix, "|", &title);
-        println!();
+    pub fn checklist_note(&mut self, note: &str) {
+        self.sink
+            .note(&self.section_stack, NotePlace::Checklist, note);
     }
 
-    pub fn checklist_result(&mut self, ok: bool) {
-        println!("|");
-        println!("|> {}", name_ok_fail(ok));
-        self.section_stack.pop();
-        self.next_is_separator = true;
+    pub fn playbook_header(&mut self, header: &str) {
+        self.sink.playbook_header(RunMode::Apply, header);
     }
 
-    pub fn checklist_title_note(&self, note: &str) {
-        let note = format!("*{note}*");
-        print_wrapped("| ", "|", &note);
-        println!();
-        println!("|");
+    pub fn playbook_check_header(&mut self, header: &str) {
+        self.sink.playbook_header(RunMode::Check, header);
     }
 
-    pub fn checklist_note(&self, note: &str) {
-        println!("|");
-        let note = format!("*{note}*");
-        print_wrapped("| ", "|", &note);
-        println!();
+    pub fn playbook_result(&mut self, header: &str, ok: bool) {
+        self.sink.playbook_result(header, ok, name_ok_fail(ok));
     }
 
-    pub fn playbook_header(&mut self, header: &str) {
-        println!("Applying playbook: {}", header);
-        self.next_is_separator = true;
+    pub fn playbook_check_result(&mut self, header: &str, drift: bool) {
+        let status = if drift { "would change" } else { "no changes" };
+        self.sink.playbook_result(header, !drift, status);
     }
 
-    pub fn playbook_result(header: &str, ok: bool) {
-        println!();
-        print!("{}", header);
-        println!();
-        println!();
-        print!(" {}", name_ok_fail(ok).to_uppercase());
-        println!();
-        println!();
+    /// Marks that output of external processes is printed inside of current
+    /// section
+    pub fn live_output(&mut self) {
+        self.sink.live_output(&self.section_stack);
     }
 
-    pub fn section_result(&mut self, ok: bool) {
-        self.put_separator();
-        println!("{}|> {}", self.section_name(), name_ok_fail(ok));
-        self.section_stack.pop();
+    /// Prints command and output of external process
+    pub fn process_output(&mut self, process: &ProcessLog) {
+        self.sink.process_output(&self.section_stack, process);
     }
 
-    pub fn process<Name, F>(&mut self, title: Name, process_fn: F) -> Result<(), ()>
-    where
-        Name: Into<String>,
-        F: FnOnce(&mut Self) -> Result<(), ()>,
-    {
-        self.push(title);
-        self.section_process();
-        print!("...applying");
-        // trying to flush, but not hard, ignoring if error
-        let _ = std::io::stdout().flush();
-        let result = process_fn(self);
-        let result_name = if result.is_ok() {
-            "...done!"
-        } else {
-            "...FAIL!"
-        };
-        println!("{result_name}");
-        self.section_stack.pop();
-        result
+    /// Prints note for current section, without closing it
+    pub fn section_note(&mut self, note: &str) {
+        self.sink
+            .note(&self.section_stack, NotePlace::Section, note);
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    pub fn section_process(&mut self) {
-        self.put_separator();
-        print!("{}", self.section_name());
-        print!("|> ");
+    #[test]
+    fn test_json_sink() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+        let mut sink = JsonSink::new(vec![]);
+        let path = vec!["Playbook".to_owned(), "1.Action".to_owned()];
+        sink.playbook_header(RunMode::Apply, "pb");
+        sink.section_start(&path, SectionKind::Checklist);
+        sink.checklist_item(&path, true, 1, "IsFile");
+        sink.note(&path, NotePlace::Checklist, "skipping");
+        sink.section_end(&path, SectionKind::Process, false);
+        sink.note(&path, NotePlace::ProcessReason, "pattern not found");
+        sink.playbook_result("pb", true, "ok");
+        let out = String::from_utf8(sink.out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"playbook_start","mode":"apply","playbook":"pb"}"#,
+                r#"{"event":"section_start","path":["Playbook","1.Action"],"kind":"checklist"}"#,
+                r#"{"event":"checklist_item","path":["Playbook","1.Action"],"index":1,"name":"IsFile","yes":true}"#,
+                r#"{"event":"note","path":["Playbook","1.Action"],"note":"skipping"}"#,
+                r#"{"event":"process_result","path":["Playbook","1.Action"],"kind":"process","ok":false}"#,
+                r#"{"event":"reason","path":["Playbook","1.Action"],"reason":"pattern not found"}"#,
+                r#"{"event":"playbook_end","playbook":"pb","ok":true,"status":"ok"}"#,
+            ]
+        );
     }
 }
@@ -0,0 +1,562 @@
+//! Small template engine for configuration files
+//!
+//! - `{{ name }}` inserts variable, `{{ item.field }}` inserts field of map
+//! - `{{ name | quote }}` inserts text variable in double quotes, `\` and `"`
+//!   are escaped with backslash and newline is written as `\n`
+//! - `{% if name %}..{% else %}..{% endif %}` inserts text if variable is true
+//!   (not empty for text, list and map), `{% if not name %}` negates it
+//! - `{% for item in list %}..{% endfor %}` inserts text for each item of list
+//! - `\{{` and `\{%` insert `{{` and `{%` as is
+//! - newline right after `%}` is removed, so tags can be placed on own lines
+//!
+//! Values are inserted as is without `quote`, nothing is escaped, so value
+//! containing `{{`, newline or characters special for target format is
+//! copied to output unchanged
+//!
+//! Undefined variable inserts nothing (and it is false for `if`), in strict
+//! mode (see [Template::strict]) rendering fails instead
+
+use std::collections::BTreeMap;
+
+/// Value of template variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(Vars),
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Text(text) => !text.is_empty(),
+            Value::Bool(b) => *b,
+            Value::List(items) => !items.is_empty(),
+            Value::Map(vars) => !vars.0.is_empty(),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+macro_rules! number_value {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(value: $t) -> Self {
+                Value::Text(value.to_string())
+            }
+        })*
+    };
+}
+
+number_value!(i32, i64, u16, u32, u64, usize);
+
+impl<T> From<Vec<T>> for Value
+where
+    T: Into<Value>,
+{
+    fn from(value: Vec<T>) -> Self {
+        Value::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for Value
+where
+    T: Into<Value>,
+{
+    fn from(value: [T; N]) -> Self {
+        Value::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<Vars> for Value {
+    fn from(value: Vars) -> Self {
+        Value::Map(value)
+    }
+}
+
+/// Variables used to render template
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vars(BTreeMap<String, Value>);
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets variable, can be used multiple times
+    pub fn var<Name, V>(mut self, name: Name, value: V) -> Self
+    where
+        Name: Into<String>,
+        V: Into<Value>,
+    {
+        self.0.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+}
+
+/// init [Vars]
+pub fn vars() -> Vars {
+    Vars::new()
+}
+
+/// Template text, it is parsed when rendered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    strict: bool,
+}
+
+impl Template {
+    pub fn new(source: String) -> Self {
+        Self {
+            source,
+            strict: false,
+        }
+    }
+
+    /// Rendering fails on undefined variables
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Renders template, fails if template is incorrect (or variable is
+    /// undefined in strict mode)
+    pub fn render(&self, vars: &Vars) -> Result<String, String> {
+        let nodes = parse(&self.source)?;
+        let mut renderer = Renderer {
+            vars,
+            strict: self.strict,
+            scope: vec![],
+            output: String::new(),
+        };
+        renderer.render(&nodes)?;
+        Ok(renderer.output)
+    }
+}
+
+/// init [Template]
+pub fn template<Source>(source: Source) -> Template
+where
+    Source: Into<String>,
+{
+    Template::new(source.into())
+}
+
+impl From<&str> for Template {
+    fn from(value: &str) -> Self {
+        template(value)
+    }
+}
+
+impl From<String> for Template {
+    fn from(value: String) -> Self {
+        template(value)
+    }
+}
+
+/// Variable name with names of fields, like `item.field`
+type VarPath = Vec<String>;
+
+enum Token {
+    Text(String),
+    Var(VarPath, bool, usize),
+    Tag(Vec<String>, usize),
+}
+
+enum Node {
+    Text(String),
+    /// variable, `true` if it is quoted
+    Var(VarPath, bool, usize),
+    If {
+        path: VarPath,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+    For {
+        name: String,
+        path: VarPath,
+        body: Vec<Node>,
+        line: usize,
+    },
+}
+
+fn var_path(expr: &str, line: usize) -> Result<VarPath, String> {
+    let path: VarPath = expr.split('.').map(str::to_owned).collect();
+    let valid = |name: &String| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if path.iter().all(valid) {
+        Ok(path)
+    } else {
+        Err(format!("line {line}: incorrect variable `{expr}`"))
+    }
+}
+
+/// Parses `{{ }}` content, returns variable and `true` if it is quoted
+fn var_expr(expr: &str, line: usize) -> Result<(VarPath, bool), String> {
+    match expr.split_once('|') {
+        None => Ok((var_path(expr, line)?, false)),
+        Some((path, filter)) => match filter.trim() {
+            "quote" => Ok((var_path(path.trim(), line)?, true)),
+            filter => Err(format!("line {line}: unknown filter `{filter}`")),
+        },
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    // line of `counted` offset, advanced as tokens are found
+    let mut line = 1;
+    let mut counted = 0;
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut pos = 0;
+    while let Some(found) = source[pos..].find('{') {
+        let start = pos + found;
+        let rest = &source[start..];
+        let close = if rest.starts_with("{{") {
+            "}}"
+        } else if rest.starts_with("{%") {
+            "%}"
+        } else {
+            text.push_str(&source[pos..=start]);
+            pos = start + 1;
+            continue;
+        };
+        if source[..start].ends_with('\\') {
+            // escaped, inserted as is without backslash
+            text.push_str(&source[pos..start - 1]);
+            text.push_str(&rest[..2]);
+            pos = start + 2;
+            continue;
+        }
+        text.push_str(&source[pos..start]);
+        line += source[counted..start].matches('\n').count();
+        counted = start;
+        let Some(len) = rest[2..].find(close) else {
+            return Err(format!("line {line}: `{}` is not closed", &rest[..2]));
+        };
+        let inner = rest[2..2 + len].trim();
+        pos = start + 2 + len + 2;
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+        if close == "}}" {
+            let (path, quoted) = var_expr(inner, line)?;
+            tokens.push(Token::Var(path, quoted, line));
+        } else {
+            tokens.push(Token::Tag(
+                inner.split_whitespace().map(str::to_owned).collect(),
+                line,
+            ));
+            if source[pos..].starts_with('\n') {
+                pos += 1;
+            } else if source[pos..].starts_with("\r\n") {
+                pos += 2;
+            }
+        }
+    }
+    text.push_str(&source[pos..]);
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    Ok(tokens)
+}
+
+/// Parses nodes until one of `until` tags, returns nodes and found tag
+fn parse_nodes<Tokens>(
+    tokens: &mut Tokens,
+    until: &[&str],
+) -> Result<(Vec<Node>, Option<String>), String>
+where
+    Tokens: Iterator<Item = Token>,
+{
+    let mut nodes = vec![];
+    while let Some(token) = tokens.next() {
+        let (words, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Var(path, quoted, line) => {
+                nodes.push(Node::Var(path, quoted, line));
+                continue;
+            }
+            Token::Tag(words, line) => (words, line),
+        };
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words[..] {
+            [tag] if until.contains(&tag) => return Ok((nodes, Some(tag.to_owned()))),
+            ["if", expr] | ["if", "not", expr] => {
+                let path = var_path(expr, line)?;
+                let not_closed = || format!("line {line}: `if` is not closed");
+                let (then, end) = parse_nodes(tokens, &["else", "endif"])?;
+                let otherwise = match end.as_deref() {
+                    Some("else") => match parse_nodes(tokens, &["endif"])? {
+                        (otherwise, Some(_)) => otherwise,
+                        (_, None) => return Err(not_closed()),
+                    },
+                    Some(_) => vec![],
+                    None => return Err(not_closed()),
+                };
+                nodes.push(Node::If {
+                    path,
+                    negate: words.len() == 3,
+                    then,
+                    otherwise,
+                    line,
+                });
+            }
+            ["for", name, "in", expr] => {
+                let name = var_path(name, line)?.join(".");
+                let path = var_path(expr, line)?;
+                let (body, end) = parse_nodes(tokens, &["endfor"])?;
+                if end.is_none() {
+                    return Err(format!("line {line}: `for` is not closed"));
+                }
+                nodes.push(Node::For {
+                    name,
+                    path,
+                    body,
+                    line,
+                });
+            }
+            _ => return Err(format!("line {line}: unexpected tag `{}`", words.join(" "))),
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, String> {
+    let mut tokens = tokenize(source)?.into_iter();
+    Ok(parse_nodes(&mut tokens, &[])?.0)
+}
+
+struct Renderer<'a> {
+    vars: &'a Vars,
+    strict: bool,
+    /// loop variables, innermost last
+    scope: Vec<(String, &'a Value)>,
+    output: String,
+}
+
+impl<'a> Renderer<'a> {
+    fn lookup(&self, path: &VarPath, line: usize) -> Result<Option<&'a Value>, String> {
+        let (name, fields) = path.split_first().expect("variable path is not empty");
+        let mut value = self
+            .scope
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| *v)
+            .or_else(|| self.vars.get(name));
+        for field in fields {
+            value = match value {
+                Some(Value::Map(vars)) => vars.get(field),
+                _ => None,
+            };
+        }
+        if value.is_none() && self.strict {
+            return Err(format!(
+                "line {line}: undefined variable `{}`",
+                path.join(".")
+            ));
+        }
+        Ok(value)
+    }
+
+    fn render(&mut self, nodes: &[Node]) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Var(path, quoted, line) => match self.lookup(path, *line)? {
+                    Some(Value::Text(text)) if *quoted => self.output.push_str(&quote(text)),
+                    Some(Value::Text(text)) => self.output.push_str(text),
+                    Some(Value::Bool(b)) => self.output.push_str(&b.to_string()),
+                    Some(_) => {
+                        return Err(format!(
+                            "line {line}: variable `{}` is not a text",
+                            path.join(".")
+                        ))
+                    }
+                    None => {}
+                },
+                Node::If {
+                    path,
+                    negate,
+                    then,
+                    otherwise,
+                    line,
+                } => {
+                    let value = self.lookup(path, *line)?.is_some_and(Value::is_true);
+                    self.render(if value != *negate { then } else { otherwise })?;
+                }
+                Node::For {
+                    name,
+                    path,
+                    body,
+                    line,
+                } => match self.lookup(path, *line)? {
+                    Some(Value::List(items)) => {
+                        for item in items {
+                            self.scope.push((name.clone(), item));
+                            let result = self.render(body);
+                            self.scope.pop();
+                            result?;
+                        }
+                    }
+                    Some(_) => {
+                        return Err(format!(
+                            "line {line}: variable `{}` is not a list",
+                            path.join(".")
+                        ))
+                    }
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = vars()
+            .var("domain", "example.com")
+            .var("port", 443)
+            .var("tls", true)
+            .var("empty", "")
+            .var(
+                "upstreams",
+                vec![
+                    vars().var("host", "a").var("weight", 1),
+                    vars().var("host", "b").var("weight", 2),
+                ],
+            );
+        let render = |source: &str| template(source).render(&vars);
+        assert_eq!(
+            render("server_name {{ domain }}:{{port}};"),
+            Ok("server_name example.com:443;".to_owned())
+        );
+        assert_eq!(
+            render("{% if tls %}\nssl on;\n{% else %}\nssl off;\n{% endif %}\n"),
+            Ok("ssl on;\n".to_owned())
+        );
+        assert_eq!(
+            render("{% if not empty %}no{% else %}yes{% endif %}{% if missing %}no{% endif %}"),
+            Ok("no".to_owned())
+        );
+        assert_eq!(
+            render("{% for u in upstreams %}\nserver {{ u.host }} weight={{ u.weight }};\n{% endfor %}\n"),
+            Ok("server a weight=1;\nserver b weight=2;\n".to_owned())
+        );
+        assert_eq!(
+            render("\\{{ domain }} \\{% if %} { {{ missing }}}"),
+            Ok("{{ domain }} {% if %} { }".to_owned())
+        );
+        assert_eq!(
+            template("a\n{{ missing.field }}").strict().render(&vars),
+            Err("line 2: undefined variable `missing.field`".to_owned())
+        );
+        assert_eq!(
+            template("{{ domain }}").strict().render(&vars),
+            Ok("example.com".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let vars = vars().var("value", "a \"b\" \\ {{ c }}\nd");
+        assert_eq!(
+            template("x = {{ value | quote }}\ny = {{ value }}").render(&vars),
+            Ok("x = \"a \\\"b\\\" \\\\ {{ c }}\\nd\"\ny = a \"b\" \\ {{ c }}\nd".to_owned())
+        );
+        assert_eq!(
+            template("{{ value | upper }}").render(&vars),
+            Err("line 1: unknown filter `upper`".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let vars = vars().var("list", vec!["a"]).var("text", "a");
+        let render = |source: &str| template(source).render(&vars);
+        assert_eq!(
+            render("{{ text"),
+            Err("line 1: `{{` is not closed".to_owned())
+        );
+        assert_eq!(
+            render("{{ a b }}"),
+            Err("line 1: incorrect variable `a b`".to_owned())
+        );
+        assert_eq!(
+            render("\n{% if text %}"),
+            Err("line 2: `if` is not closed".to_owned())
+        );
+        assert_eq!(
+            render("{% for i in list %}{% endif %}"),
+            Err("line 1: unexpected tag `endif`".to_owned())
+        );
+        assert_eq!(
+            render("a\n\n{% if text %}\n{{ b c }}"),
+            Err("line 4: incorrect variable `b c`".to_owned())
+        );
+        assert_eq!(
+            render("{% else %}"),
+            Err("line 1: unexpected tag `else`".to_owned())
+        );
+        assert_eq!(
+            render("{{ list }}"),
+            Err("line 1: variable `list` is not a text".to_owned())
+        );
+        assert_eq!(
+            render("{% for i in text %}{% endfor %}"),
+            Err("line 1: variable `text` is not a list".to_owned())
+        );
+    }
+}